@@ -0,0 +1,600 @@
+//! JSON API, mounted under `/api/v1`
+//!
+//! Tasks and dependencies are identified by their uid here, not by the
+//! human-readable ID shown in the task grid.
+use std::collections::{BTreeSet, HashMap};
+
+use chrono::NaiveDate;
+use itertools::Itertools;
+use rocket::{
+    http::Status,
+    response::{self, Responder},
+    serde::json::Json,
+    Request, Route, State,
+};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use planny::{
+    calendar::{today, Calendar},
+    datamodel::{
+        is_part_of, Deadline, DependenciesOrList, Dependency, Distribution, PeopleOrCount,
+        PoolMember, Project, Risk, Scheduling, Task, TaskStatus, TaskType, TaskUpdate,
+    },
+    forecast::MAX_ITERATIONS,
+    ids::unique_id,
+    pool::{allocations, overallocation_warnings, share_out, Allocation},
+    portfolio::{self, upstream, PortfolioForecast},
+    report::{self, Forecast},
+};
+
+use crate::Db;
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        list_projects,
+        create_project,
+        get_project,
+        patch_project,
+        delete_project,
+        list_tasks,
+        create_task,
+        get_task,
+        patch_task,
+        delete_task,
+        put_dependency,
+        delete_dependency,
+        get_forecast,
+        get_portfolio,
+        list_pool,
+        put_pool_member,
+        delete_pool_member,
+    ]
+}
+
+pub enum ApiError {
+    NotFound,
+    BadRequest(String),
+    Conflict(String),
+    Internal(anyhow::Error),
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        ApiError::Internal(e)
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let (status, error) = match self {
+            ApiError::NotFound => (Status::NotFound, "Not found".to_owned()),
+            ApiError::BadRequest(msg) => (Status::BadRequest, msg),
+            ApiError::Conflict(msg) => (Status::Conflict, msg),
+            ApiError::Internal(e) => {
+                eprintln!("API error: {:?}", e);
+                (Status::InternalServerError, e.to_string())
+            }
+        };
+        (status, Json(ErrorBody { error })).respond_to(req)
+    }
+}
+
+type ApiResult<T> = Result<T, ApiError>;
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct ProjectInput {
+    name: Option<String>,
+    /// A list of people, or how many full-time people there are
+    people: Option<PeopleOrCount>,
+    seed: Option<u64>,
+    iterations: Option<u32>,
+    #[serde(deserialize_with = "double_option")]
+    tolerance: Option<Option<f64>>,
+    /// Chance of making a deadline below which a milestone is flagged, 0 to 1
+    confidence: Option<f64>,
+    /// `random`, `priority`, `longest-path` or `shortest-task`
+    scheduling: Option<Scheduling>,
+    /// Work added for every extra person on a task, as a fraction of the task
+    efficiency_loss: Option<f64>,
+    /// Replaces the whole calendar
+    calendar: Option<Calendar>,
+}
+
+impl ProjectInput {
+    /// People can only be someone in the pool who is there
+    fn check_pool(&self, pool: &[PoolMember]) -> ApiResult<()> {
+        let Some(PeopleOrCount::People(people)) = &self.people else {
+            return Ok(());
+        };
+        match people
+            .iter()
+            .filter_map(|p| p.pool.as_ref())
+            .find(|uid| !pool.iter().any(|m| m.uid == **uid))
+        {
+            Some(uid) => Err(ApiError::BadRequest(format!(
+                "No such pool member: {}",
+                uid
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    fn apply(&self, project: &mut Project) {
+        if let Some(name) = &self.name {
+            project.name = name.clone();
+        }
+        if let Some(people) = &self.people {
+            project.people = people.clone().into();
+            project.ensure_people();
+        }
+        if let Some(seed) = self.seed {
+            project.seed = seed;
+        }
+        if let Some(iterations) = self.iterations {
+            project.iterations = iterations.clamp(1, MAX_ITERATIONS);
+        }
+        if let Some(tolerance) = self.tolerance {
+            project.tolerance = tolerance.filter(|t| *t > 0.0);
+        }
+        if let Some(confidence) = self.confidence.filter(|c| c.is_finite()) {
+            project.confidence = confidence.clamp(0.0, 1.0);
+        }
+        if let Some(scheduling) = self.scheduling {
+            project.scheduling = scheduling;
+        }
+        if let Some(loss) = self.efficiency_loss.filter(|l| l.is_finite()) {
+            project.efficiency_loss = loss.max(0.0);
+        }
+        if let Some(calendar) = &self.calendar {
+            project.calendar = calendar.clone();
+        }
+    }
+}
+
+/// Fields that are left out are not changed
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct PoolMemberInput {
+    name: Option<String>,
+    /// Fraction of full time they work, to be split between projects
+    capacity: Option<f64>,
+}
+
+/// Fields that are left out are not changed, fields that are `null` are cleared
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct TaskInput {
+    r#type: Option<TaskType>,
+    id: Option<String>,
+    title: Option<String>,
+    #[serde(deserialize_with = "double_option")]
+    estimate: Option<Option<f64>>,
+    #[serde(deserialize_with = "double_option")]
+    risk: Option<Option<Risk>>,
+    distribution: Option<Distribution>,
+    #[serde(deserialize_with = "double_option")]
+    optimistic: Option<Option<f64>>,
+    #[serde(deserialize_with = "double_option")]
+    pessimistic: Option<Option<f64>>,
+    #[serde(deserialize_with = "double_option")]
+    sigma: Option<Option<f64>>,
+    /// Replaces all dependencies of the task, a list of uids or kinds and lags by uid
+    dependencies: Option<DependenciesOrList>,
+    skills: Option<BTreeSet<String>>,
+    /// Uid of a person on the project
+    #[serde(deserialize_with = "double_option")]
+    assignee: Option<Option<String>>,
+    status: Option<TaskStatus>,
+    #[serde(deserialize_with = "double_option")]
+    started: Option<Option<NaiveDate>>,
+    #[serde(deserialize_with = "double_option")]
+    finished: Option<Option<NaiveDate>>,
+    /// Days of work left on a task in progress
+    #[serde(deserialize_with = "double_option")]
+    remaining: Option<Option<f64>>,
+    /// `YYYY-MM-DD`, or a number of working days into the project
+    #[serde(deserialize_with = "double_option")]
+    deadline: Option<Option<Deadline>>,
+    /// Uid of the epic the task is part of
+    #[serde(deserialize_with = "double_option")]
+    parent: Option<Option<String>>,
+    /// 1 goes first when scheduling by priority
+    #[serde(deserialize_with = "double_option")]
+    priority: Option<Option<u32>>,
+    /// How many people can work on the task at once, at least 1
+    #[serde(deserialize_with = "double_option")]
+    max_people: Option<Option<u32>>,
+}
+
+/// Distinguish between a missing field (`None`) and an explicit `null` (`Some(None)`)
+fn double_option<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+#[get("/projects")]
+fn list_projects(db: &State<Db>) -> ApiResult<Json<Vec<Project>>> {
+    let mut projects: Vec<Project> = db.0.projects()?.iter_all().cloned().collect();
+    projects.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(Json(projects))
+}
+
+#[post("/projects", data = "<input>")]
+fn create_project(input: Json<ProjectInput>, db: &State<Db>) -> ApiResult<(Status, Json<Project>)> {
+    input.check_pool(&db.0.pool()?)?;
+    let uid = db.0.new_project()?;
+    db.0.with_project(&uid, |project| input.apply(project))?;
+    let project = db.0.project(&uid)?.ok_or(ApiError::NotFound)?;
+    Ok((Status::Created, Json(project)))
+}
+
+#[get("/projects/<uid>")]
+fn get_project(uid: &str, db: &State<Db>) -> ApiResult<Json<Project>> {
+    Ok(Json(db.0.project(uid)?.ok_or(ApiError::NotFound)?))
+}
+
+#[patch("/projects/<uid>", data = "<input>")]
+fn patch_project(uid: &str, input: Json<ProjectInput>, db: &State<Db>) -> ApiResult<Json<Project>> {
+    db.0.project(uid)?.ok_or(ApiError::NotFound)?;
+    input.check_pool(&db.0.pool()?)?;
+    db.0.with_project(uid, |project| input.apply(project))?;
+    get_project(uid, db)
+}
+
+#[delete("/projects/<uid>")]
+fn delete_project(uid: &str, db: &State<Db>) -> ApiResult<Status> {
+    db.0.project(uid)?.ok_or(ApiError::NotFound)?;
+    db.0.delete_project(uid)?;
+    Ok(Status::NoContent)
+}
+
+#[get("/projects/<project_uid>/tasks")]
+fn list_tasks(project_uid: &str, db: &State<Db>) -> ApiResult<Json<Vec<Task>>> {
+    db.0.project(project_uid)?.ok_or(ApiError::NotFound)?;
+    let tasks = db.0.project_tasks(project_uid)?;
+    Ok(Json(tasks))
+}
+
+#[post("/projects/<project_uid>/tasks", data = "<input>")]
+fn create_task(
+    project_uid: &str,
+    input: Json<TaskInput>,
+    db: &State<Db>,
+) -> ApiResult<(Status, Json<Task>)> {
+    db.0.project(project_uid)?.ok_or(ApiError::NotFound)?;
+    let uid = unique_id();
+    upsert_task(project_uid, &uid, None, input.into_inner(), db)?;
+    Ok((Status::Created, get_task(project_uid, &uid, db)?))
+}
+
+#[get("/projects/<project_uid>/tasks/<uid>")]
+fn get_task(project_uid: &str, uid: &str, db: &State<Db>) -> ApiResult<Json<Task>> {
+    let task = db.0.task(project_uid, uid)?;
+    Ok(Json(task.ok_or(ApiError::NotFound)?))
+}
+
+#[patch("/projects/<project_uid>/tasks/<uid>", data = "<input>")]
+fn patch_task(
+    project_uid: &str,
+    uid: &str,
+    input: Json<TaskInput>,
+    db: &State<Db>,
+) -> ApiResult<Json<Task>> {
+    let existing = get_task(project_uid, uid, db)?.into_inner();
+    upsert_task(project_uid, uid, Some(&existing), input.into_inner(), db)?;
+    get_task(project_uid, uid, db)
+}
+
+#[delete("/projects/<project_uid>/tasks/<uid>")]
+fn delete_task(project_uid: &str, uid: &str, db: &State<Db>) -> ApiResult<Status> {
+    get_task(project_uid, uid, db)?;
+    db.0.delete_task(project_uid, uid)?;
+    Ok(Status::NoContent)
+}
+
+/// Without a body, the dependency is finish-to-start
+#[put(
+    "/projects/<project_uid>/tasks/<uid>/dependencies/<dep_uid>",
+    data = "<dependency>"
+)]
+fn put_dependency(
+    project_uid: &str,
+    uid: &str,
+    dep_uid: &str,
+    dependency: Option<Json<Dependency>>,
+    db: &State<Db>,
+) -> ApiResult<Json<Task>> {
+    let existing = get_task(project_uid, uid, db)?.into_inner();
+    let mut dependencies = existing.dependencies.clone();
+    dependencies.insert(
+        dep_uid.to_owned(),
+        dependency.map(|d| d.into_inner()).unwrap_or_default(),
+    );
+    let input = TaskInput {
+        dependencies: Some(DependenciesOrList::Dependencies(dependencies)),
+        ..Default::default()
+    };
+    upsert_task(project_uid, uid, Some(&existing), input, db)?;
+    get_task(project_uid, uid, db)
+}
+
+#[delete("/projects/<project_uid>/tasks/<uid>/dependencies/<dep_uid>")]
+fn delete_dependency(
+    project_uid: &str,
+    uid: &str,
+    dep_uid: &str,
+    db: &State<Db>,
+) -> ApiResult<Json<Task>> {
+    get_task(project_uid, uid, db)?;
+    db.0.with_task(project_uid, uid, |task| {
+        task.dependencies.remove(dep_uid);
+    })?;
+    get_task(project_uid, uid, db)
+}
+
+fn upsert_task(
+    project_uid: &str,
+    uid: &str,
+    existing: Option<&Task>,
+    input: TaskInput,
+    db: &State<Db>,
+) -> ApiResult<()> {
+    check_numbers(&input)?;
+    let mut add_dependencies = vec![];
+    let mut remove_dependencies = vec![];
+    if let Some(deps) = input.dependencies {
+        let mut deps: HashMap<String, Dependency> = deps.into();
+        for (dep, dependency) in &mut deps {
+            // A task of the project itself needs no project
+            dependency.project = dependency.project.take().filter(|p| p != project_uid);
+            if dep == uid && dependency.project.is_none() {
+                return Err(ApiError::BadRequest(
+                    "A task can't depend on itself".to_owned(),
+                ));
+            }
+            let dep_project = dependency.project.as_deref().unwrap_or(project_uid);
+            if db.0.task(dep_project, dep)?.is_none() {
+                return Err(ApiError::BadRequest(format!("No such task: {}", dep)));
+            }
+        }
+        let current = existing.map(|t| t.dependencies.clone()).unwrap_or_default();
+        add_dependencies = deps
+            .iter()
+            .filter(|(uid, dependency)| current.get(*uid) != Some(dependency))
+            .map(|(uid, dependency)| (uid.clone(), dependency.clone()))
+            .collect();
+        remove_dependencies = current
+            .keys()
+            .filter(|uid| !deps.contains_key(*uid))
+            .cloned()
+            .collect();
+    }
+
+    if let Some(Some(assignee)) = &input.assignee {
+        let project = db.0.project(project_uid)?.ok_or(ApiError::NotFound)?;
+        if !project.people.iter().any(|p| &p.uid == assignee) {
+            return Err(ApiError::BadRequest(format!(
+                "No such person: {}",
+                assignee
+            )));
+        }
+    }
+
+    if let Some(Some(parent)) = &input.parent {
+        let tasks = db.0.project_tasks(project_uid)?;
+        let by_uid = HashMap::from_iter(tasks.iter().map(|t| (t.uid.as_str(), t)));
+        if !by_uid.contains_key(parent.as_str()) {
+            return Err(ApiError::BadRequest(format!("No such task: {}", parent)));
+        }
+        if is_part_of(&by_uid, parent, uid) {
+            return Err(ApiError::BadRequest(
+                "A task can't be part of itself".to_owned(),
+            ));
+        }
+    }
+
+    db.0.upsert_task(TaskUpdate {
+        project_uid: project_uid.to_owned(),
+        uid: uid.to_owned(),
+        r#type: input.r#type,
+        id: input.id,
+        title: input.title,
+        estimate: input.estimate,
+        risk: input.risk,
+        distribution: input.distribution,
+        optimistic: input.optimistic,
+        pessimistic: input.pessimistic,
+        sigma: input.sigma,
+        add_dependencies,
+        remove_dependencies,
+        skills: input
+            .skills
+            .map(|skills| skills.iter().map(|s| s.to_lowercase()).collect()),
+        assignee: input.assignee,
+        status: input.status,
+        started: input.started,
+        finished: input.finished,
+        remaining: input.remaining,
+        deadline: input.deadline,
+        parent: input.parent,
+        priority: input.priority,
+        max_people: input.max_people,
+    })?;
+    Ok(())
+}
+
+/// Days and amounts can't be negative or infinite
+fn check_numbers(input: &TaskInput) -> ApiResult<()> {
+    for (name, value) in [
+        ("estimate", input.estimate),
+        ("remaining", input.remaining),
+        ("optimistic", input.optimistic),
+        ("pessimistic", input.pessimistic),
+        ("sigma", input.sigma),
+        (
+            "deadline",
+            input.deadline.map(|d| match d {
+                Some(Deadline::Day(day)) => Some(day),
+                _ => None,
+            }),
+        ),
+    ] {
+        if let Some(Some(value)) = value {
+            if !value.is_finite() || value < 0.0 {
+                return Err(ApiError::BadRequest(format!("Invalid {}: {}", name, value)));
+            }
+        }
+    }
+    if input.max_people == Some(Some(0)) {
+        return Err(ApiError::BadRequest("Invalid max_people: 0".to_owned()));
+    }
+    Ok(())
+}
+
+/// `seed` overrides the seed of the project for this forecast only
+#[get("/projects/<project_uid>/forecast?<seed>")]
+fn get_forecast(project_uid: &str, seed: Option<u64>, db: &State<Db>) -> ApiResult<Json<Forecast>> {
+    let mut project = db.0.project(project_uid)?.ok_or(ApiError::NotFound)?;
+    if let Some(seed) = seed {
+        project.seed = seed;
+    }
+    let all_tasks = db.0.tasks()?;
+    let projects = db.0.projects()?;
+    let allocations = allocations(&db.0.pool()?, projects.iter_all());
+    share_out(&mut project, &allocations);
+    let mut upstream = upstream(project_uid, &projects, &all_tasks);
+    for (p, _) in &mut upstream {
+        share_out(p, &allocations);
+    }
+    let tasks: Vec<Task> = all_tasks.into_many(project_uid).collect();
+    let mut forecast = report::forecast(&project, tasks, &upstream, today())
+        .map_err(|e| ApiError::Conflict(e.to_string()))?;
+    forecast
+        .warnings
+        .splice(0..0, overallocation_warnings(&allocations, &[project_uid]));
+    Ok(Json(forecast))
+}
+
+/// `projects` are uids, repeated like `?projects=a&projects=b`, the projects they wait for come along
+#[get("/portfolio?<projects>&<shared>")]
+fn get_portfolio(
+    projects: Vec<&str>,
+    shared: bool,
+    db: &State<Db>,
+) -> ApiResult<Json<PortfolioForecast>> {
+    let all_projects = db.0.projects()?;
+    if let Some(missing) = projects.iter().find(|p| all_projects.get1(**p).is_none()) {
+        return Err(ApiError::BadRequest(format!(
+            "No such project: {}",
+            missing
+        )));
+    }
+    let allocations = allocations(&db.0.pool()?, all_projects.iter_all());
+    let mut plans = portfolio::plans(&projects, &all_projects, &db.0.tasks()?);
+    for (p, _) in &mut plans {
+        share_out(p, &allocations);
+    }
+    let uids = plans.iter().map(|(p, _)| p.uid.as_str()).collect_vec();
+    let warnings = overallocation_warnings(&allocations, &uids);
+    let mut forecast = portfolio::forecast(plans, shared, today())
+        .map_err(|e| ApiError::Conflict(e.to_string()))?;
+    forecast.warnings.splice(0..0, warnings);
+    Ok(Json(forecast))
+}
+
+/// Everyone in the pool, with the share of their time each project counts on
+#[get("/pool")]
+fn list_pool(db: &State<Db>) -> ApiResult<Json<Vec<Allocation>>> {
+    Ok(Json(allocations(
+        &db.0.pool()?,
+        db.0.projects()?.iter_all(),
+    )))
+}
+
+/// Creates the pool member if there is none with this uid
+#[put("/pool/<uid>", data = "<input>")]
+fn put_pool_member(
+    uid: &str,
+    input: Json<PoolMemberInput>,
+    db: &State<Db>,
+) -> ApiResult<Json<PoolMember>> {
+    if let Some(capacity) = input.capacity.filter(|c| !c.is_finite() || *c < 0.0) {
+        return Err(ApiError::BadRequest(format!(
+            "Invalid capacity: {}",
+            capacity
+        )));
+    }
+    db.0.with_pool(|pool| {
+        let i = match pool.iter().position(|m| m.uid == uid) {
+            Some(i) => i,
+            None => {
+                pool.push(PoolMember {
+                    uid: uid.to_owned(),
+                    name: format!("Person {}", pool.len() + 1),
+                    ..Default::default()
+                });
+                pool.len() - 1
+            }
+        };
+        if let Some(name) = input
+            .name
+            .as_deref()
+            .map(str::trim)
+            .filter(|n| !n.is_empty())
+        {
+            pool[i].name = name.to_owned();
+        }
+        if let Some(capacity) = input.capacity {
+            pool[i].capacity = capacity;
+        }
+    })?;
+    let pool = db.0.pool()?;
+    let member = pool.into_iter().find(|m| m.uid == uid);
+    Ok(Json(member.ok_or(ApiError::NotFound)?))
+}
+
+/// People on projects who were this member only work on their project from then on
+#[delete("/pool/<uid>")]
+fn delete_pool_member(uid: &str, db: &State<Db>) -> ApiResult<Status> {
+    if !db.0.pool()?.iter().any(|m| m.uid == uid) {
+        return Err(ApiError::NotFound);
+    }
+    db.0.with_pool(|pool| pool.retain(|m| m.uid != uid))?;
+    Ok(Status::NoContent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(json: &str) -> ApiResult<()> {
+        check_numbers(&serde_json::from_str(json).unwrap())
+    }
+
+    #[test]
+    fn numbers_must_be_finite_and_not_negative() {
+        assert!(check(r#"{"estimate": 3, "remaining": 0, "sigma": 0.5}"#).is_ok());
+        assert!(check(r#"{"estimate": null, "deadline": null}"#).is_ok());
+        assert!(matches!(
+            check(r#"{"estimate": -1}"#),
+            Err(ApiError::BadRequest(_))
+        ));
+        assert!(matches!(
+            check(r#"{"optimistic": -0.5}"#),
+            Err(ApiError::BadRequest(_))
+        ));
+        assert!(matches!(
+            check(r#"{"max_people": 0}"#),
+            Err(ApiError::BadRequest(_))
+        ));
+    }
+}
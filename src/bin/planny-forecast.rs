@@ -0,0 +1,260 @@
+//! Forecast a project without starting the web server
+//!
+//! Reads a project from the database, or from a standalone plan file with the
+//! same shape as a snapshot: `{"project": {...}, "tasks": [...]}`.
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs, io,
+    path::PathBuf,
+};
+
+use anyhow::{bail, Context, Result};
+use chrono::NaiveDate;
+use clap::Parser;
+use itertools::Itertools;
+use planny::{
+    calendar::today,
+    datamodel::{Project, Scheduling, Task},
+    db::Database,
+    pool::overallocation_warnings,
+    portfolio::{project_plan, ProjectPlan},
+    report::{self, Forecast},
+};
+use serde::Deserialize;
+
+#[derive(Parser, Debug)]
+#[command(about = "Forecast when the tasks of a project finish")]
+struct Args {
+    /// Uid or name of the project to forecast
+    #[arg(required_unless_present = "plan")]
+    project: Option<String>,
+
+    /// Database to read the project from
+    #[arg(short, long, default_value = "db.json")]
+    database: String,
+
+    /// Read a standalone plan file instead of the database
+    #[arg(short, long, conflicts_with_all = ["project", "database"])]
+    plan: Option<PathBuf>,
+
+    /// Seed for the simulation, instead of the seed of the project
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// How many times to simulate the project, instead of the setting of the project
+    #[arg(long)]
+    iterations: Option<u32>,
+
+    /// Stop early once the estimates move less than this fraction, e.g. 0.01
+    #[arg(long)]
+    tolerance: Option<f64>,
+
+    /// Warn about milestones less likely than this to make their deadline, e.g. 0.9
+    #[arg(long)]
+    confidence: Option<f64>,
+
+    /// Which ready task gets picked up first: random, priority, longest-path or shortest-task
+    #[arg(long)]
+    scheduling: Option<Scheduling>,
+
+    /// Forecast as if it were this day, YYYY-MM-DD, instead of today
+    #[arg(long)]
+    today: Option<NaiveDate>,
+
+    /// Print the full forecast as JSON instead of a table
+    #[arg(long)]
+    json: bool,
+}
+
+/// A project and its tasks, as stored in a snapshot
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct PlanFile {
+    project: Project,
+    tasks: Vec<Task>,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+
+    let Plan {
+        mut project,
+        tasks,
+        upstream,
+        warnings,
+    } = match &args.plan {
+        Some(path) => read_plan(path)?,
+        None => read_project(&args.database, args.project.as_deref().unwrap_or_default())?,
+    };
+    if let Some(seed) = args.seed {
+        project.seed = seed;
+    }
+    if let Some(iterations) = args.iterations {
+        project.iterations = iterations;
+    }
+    if let Some(tolerance) = args.tolerance {
+        project.tolerance = Some(tolerance);
+    }
+    if let Some(confidence) = args.confidence {
+        project.confidence = confidence;
+    }
+    if let Some(scheduling) = args.scheduling {
+        project.scheduling = scheduling;
+    }
+    let mut forecast =
+        report::forecast(&project, tasks, &upstream, args.today.unwrap_or_else(today))?;
+    forecast.warnings.splice(0..0, warnings);
+
+    if args.json {
+        serde_json::to_writer_pretty(io::stdout().lock(), &forecast)?;
+        println!();
+    } else {
+        print_table(&forecast);
+        for warning in &forecast.warnings {
+            eprintln!("Warning: {}", warning);
+        }
+    }
+    Ok(())
+}
+
+/// A project and its tasks, ready to forecast
+struct Plan {
+    project: Project,
+    tasks: Vec<Task>,
+    /// The projects it waits for
+    upstream: Vec<(Project, Vec<Task>)>,
+    /// About the people it shares with other projects
+    warnings: Vec<String>,
+}
+
+fn read_plan(path: &PathBuf) -> Result<Plan> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("Reading {}", path.display()))?;
+    let mut plan: PlanFile =
+        serde_json::from_str(&contents).with_context(|| format!("Parsing {}", path.display()))?;
+
+    // Hand-written plans can leave out uids and refer to dependencies by ID,
+    // tasks without an ID get the first free one counting from their position
+    let mut ids: HashSet<String> = plan.tasks.iter().map(|t| t.id.clone()).collect();
+    let mut uids: HashSet<String> = plan.tasks.iter().map(|t| t.uid.clone()).collect();
+    for (i, task) in plan.tasks.iter_mut().enumerate() {
+        if task.id.is_empty() {
+            task.id = (i + 1..)
+                .map(|n| format!("T{}", n))
+                .find(|id| !ids.contains(id))
+                .unwrap_or_default();
+            ids.insert(task.id.clone());
+        }
+        if task.uid.is_empty() {
+            task.uid = (0..)
+                .map(|n| match n {
+                    0 => task.id.clone(),
+                    n => format!("{}-{}", task.id, n),
+                })
+                .find(|uid| !uids.contains(uid))
+                .unwrap_or_default();
+            uids.insert(task.uid.clone());
+        }
+    }
+    Ok(Plan {
+        project: plan.project,
+        tasks: plan.tasks,
+        upstream: vec![],
+        warnings: vec![],
+    })
+}
+
+fn read_project(database: &str, project: &str) -> Result<Plan> {
+    let db = Database::new(database.to_owned(), false)?;
+    let projects = db.projects()?;
+    let project = match projects.get1(project) {
+        Some(p) => p.clone(),
+        None => {
+            let mut matches = projects.iter_all().filter(|p| p.name == project);
+            match (matches.next(), matches.next()) {
+                (Some(p), None) => p.clone(),
+                (Some(_), Some(_)) => bail!("More than one project is called {:?}", project),
+                (None, _) => bail!("No such project: {:?}", project),
+            }
+        }
+    };
+    let ProjectPlan {
+        project,
+        tasks,
+        upstream,
+        allocations,
+    } = project_plan(project, &projects, &db.tasks()?, &db.pool()?);
+    let warnings = overallocation_warnings(&allocations, &[&project.uid]);
+    Ok(Plan {
+        project,
+        tasks,
+        upstream,
+        warnings,
+    })
+}
+
+/// Prints dates when the project has a start date, days from the start otherwise
+fn print_table(forecast: &Forecast) {
+    let q = |days: &BTreeMap<&str, f64>, dates: &BTreeMap<&str, NaiveDate>, name: &str| match (
+        dates.get(name),
+        days.get(name),
+    ) {
+        (Some(date), _) => date.to_string(),
+        (None, Some(days)) => format!("{:.1}", days),
+        (None, None) => "-".to_owned(),
+    };
+
+    let id_width = forecast
+        .tasks
+        .iter()
+        .map(|t| t.id.chars().count())
+        .chain(["Total".len()])
+        .max()
+        .unwrap_or_default();
+
+    println!(
+        "{:<id_width$}  {:>10}  {:>10}  {:>10}  {:>8}  {:>11}  Title",
+        "ID", "Start p50", "Finish p50", "Finish p90", "Critical", "Sensitivity"
+    );
+    for task in &forecast.tasks {
+        println!(
+            "{:<id_width$}  {:>10}  {:>10}  {:>10}  {:>7.0}%  {:>+11.2}  {}",
+            task.id,
+            q(&task.start, &task.start_dates, "p50"),
+            q(&task.finish, &task.finish_dates, "p50"),
+            q(&task.finish, &task.finish_dates, "p90"),
+            task.criticality * 100.0,
+            task.sensitivity,
+            task.title,
+        );
+    }
+    println!(
+        "{:<id_width$}  {:>10}  {:>10}  {:>10}",
+        "Total",
+        "",
+        q(&forecast.finish, &forecast.finish_dates, "p50"),
+        q(&forecast.finish, &forecast.finish_dates, "p90"),
+    );
+
+    let deadlines = forecast
+        .tasks
+        .iter()
+        .filter_map(|t| Some((t, t.deadline?, t.deadline_chance?)))
+        .collect_vec();
+    if !deadlines.is_empty() {
+        println!();
+        println!(
+            "{:<id_width$}  {:>10}  {:>7}  Title",
+            "ID", "Deadline", "Chance"
+        );
+        for (task, deadline, chance) in deadlines {
+            println!(
+                "{:<id_width$}  {:>10}  {:>6.0}%  {}",
+                task.id,
+                deadline.to_string(),
+                chance * 100.0,
+                task.title
+            );
+        }
+    }
+}
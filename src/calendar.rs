@@ -0,0 +1,189 @@
+use std::collections::{BTreeSet, HashSet};
+
+use chrono::{Datelike, Days, Local, NaiveDate, Weekday};
+use serde::{Deserialize, Serialize};
+
+/// The local date, forecasts plan the work that is left from today on
+pub fn today() -> NaiveDate {
+    Local::now().date_naive()
+}
+
+/// The working days of a project, to turn forecasts in days into dates
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct Calendar {
+    /// Without a start date, forecasts are in working days from the start
+    pub start: Option<NaiveDate>,
+    pub working_days: Vec<Weekday>,
+    /// Days off that fall on a working day, e.g. public holidays
+    pub holidays: BTreeSet<NaiveDate>,
+}
+
+impl Default for Calendar {
+    fn default() -> Self {
+        Self {
+            start: None,
+            working_days: vec![
+                Weekday::Mon,
+                Weekday::Tue,
+                Weekday::Wed,
+                Weekday::Thu,
+                Weekday::Fri,
+            ],
+            holidays: Default::default(),
+        }
+    }
+}
+
+impl Calendar {
+    pub fn is_working_day(&self, date: NaiveDate) -> bool {
+        self.is_working_weekday(date.weekday()) && !self.holidays.contains(&date)
+    }
+
+    /// All working days from the start of the project on
+    pub fn working_days(&self) -> impl Iterator<Item = NaiveDate> + '_ {
+        self.working_days_from(0)
+    }
+
+    /// All working days from working day `n` on
+    pub fn working_days_from(&self, n: usize) -> impl Iterator<Item = NaiveDate> + '_ {
+        self.working_day(n)
+            .into_iter()
+            .flat_map(|first| first.iter_days())
+            .filter(|d| self.is_working_day(*d))
+    }
+
+    /// The date of working day `n`, counting from 0
+    ///
+    /// Skips whole weeks at a time, and only walks the days that are left.
+    pub fn working_day(&self, n: usize) -> Option<NaiveDate> {
+        let mut date = self.start?;
+        let mut left = n as i64;
+        let per_week = self.days_per_week();
+        while left >= per_week {
+            let weeks = left / per_week;
+            let end = date.checked_add_days(Days::new(weeks as u64 * 7))?;
+            left -= weeks * per_week - self.holidays_between(date, end);
+            date = end;
+        }
+        loop {
+            if self.is_working_day(date) {
+                if left == 0 {
+                    return Some(date);
+                }
+                left -= 1;
+            }
+            date = date.succ_opt()?;
+        }
+    }
+
+    /// How many working days into the project `date` is, the inverse of `start_date`
+    pub fn offset(&self, date: NaiveDate) -> Option<f64> {
+        let start = self.start?;
+        if date <= start {
+            return Some(0.0);
+        }
+        let days = (date - start).num_days();
+        let weeks = days / 7;
+        let rest = start
+            .checked_add_days(Days::new(weeks as u64 * 7))?
+            .iter_days()
+            .take((days % 7) as usize)
+            .filter(|d| self.is_working_weekday(d.weekday()))
+            .count() as i64;
+        Some((weeks * self.days_per_week() + rest - self.holidays_between(start, date)) as f64)
+    }
+
+    /// Working days in a week, not counting holidays
+    fn days_per_week(&self) -> i64 {
+        match self.working_days.iter().collect::<HashSet<_>>().len() {
+            0 => 7,
+            n => n as i64,
+        }
+    }
+
+    fn is_working_weekday(&self, weekday: Weekday) -> bool {
+        // A week without working days would never end
+        self.working_days.is_empty() || self.working_days.contains(&weekday)
+    }
+
+    /// Holidays from `start` up to `end` that would have been working days
+    fn holidays_between(&self, start: NaiveDate, end: NaiveDate) -> i64 {
+        self.holidays
+            .range(start..end)
+            .filter(|d| self.is_working_weekday(d.weekday()))
+            .count() as i64
+    }
+
+    /// The day something that starts `offset` working days into the project starts on
+    pub fn start_date(&self, offset: f64) -> Option<NaiveDate> {
+        self.working_day(offset.max(0.0).floor() as usize)
+    }
+
+    /// The last day of work for something that ends `offset` working days into the project
+    ///
+    /// Something that takes exactly one day ends on the day it started.
+    pub fn finish_date(&self, offset: f64) -> Option<NaiveDate> {
+        self.working_day((offset.ceil() as usize).max(1) - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    /// Walks every day, what the arithmetic has to agree with
+    fn walked(calendar: &Calendar) -> Vec<NaiveDate> {
+        let start = calendar.start.unwrap();
+        start
+            .iter_days()
+            .filter(|d| calendar.is_working_day(*d))
+            .take(600)
+            .collect()
+    }
+
+    #[test]
+    fn working_days_and_offsets_agree_with_walking() {
+        let start = date(2026, 1, 1);
+        let holidays = BTreeSet::from([
+            date(2025, 12, 25),
+            date(2026, 1, 1),
+            date(2026, 1, 3),
+            date(2026, 4, 6),
+            date(2026, 5, 25),
+            date(2026, 12, 25),
+        ]);
+        for working_days in [
+            Calendar::default().working_days,
+            vec![Weekday::Sat, Weekday::Sun, Weekday::Sat],
+            vec![],
+        ] {
+            let calendar = Calendar {
+                start: Some(start),
+                working_days,
+                holidays: holidays.clone(),
+            };
+            let days = walked(&calendar);
+            for (n, day) in days.iter().enumerate() {
+                assert_eq!(calendar.working_day(n), Some(*day), "day {}", n);
+                assert_eq!(calendar.offset(*day), Some(n as f64), "{}", day);
+            }
+            for before in start.pred_opt().unwrap().iter_days().take(500) {
+                let expected = days.iter().take_while(|d| **d < before).count() as f64;
+                assert_eq!(calendar.offset(before), Some(expected), "{}", before);
+            }
+            assert_eq!(calendar.working_days_from(7).next(), Some(days[7]));
+        }
+    }
+
+    #[test]
+    fn without_a_start_there_are_no_dates() {
+        let calendar = Calendar::default();
+        assert_eq!(calendar.working_day(3), None);
+        assert_eq!(calendar.offset(date(2026, 1, 5)), None);
+    }
+}
@@ -0,0 +1,515 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use chrono::NaiveDate;
+use itertools::Itertools;
+use serde::Serialize;
+
+use crate::{
+    datamodel::{roughly_sort_tasks, Dependency, Person, Project, Task, TaskStatus},
+    pool::{share_out, Allocation},
+    portfolio::simulate_project,
+    report::staffing_warnings,
+};
+
+/// A project and its tasks, either a snapshot or the current state
+pub struct Plan {
+    pub name: String,
+    pub project: Project,
+    pub tasks: Vec<Task>,
+    /// The day the plan is forecast as of, when the snapshot was taken
+    pub today: NaiveDate,
+    /// The projects it waits for, see `portfolio::project_plan`, snapshots don't keep them
+    pub upstream: Vec<(Project, Vec<Task>)>,
+    /// Of everyone in the pool, to give the people the project shares only their part
+    pub allocations: Vec<Allocation>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct Finish {
+    pub p50: f64,
+    pub p90: f64,
+    /// Only when the plan has a start date
+    pub p50_date: Option<NaiveDate>,
+    pub p90_date: Option<NaiveDate>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TaskComparison {
+    pub id: String,
+    pub title: String,
+    pub before: Option<Finish>,
+    pub after: Option<Finish>,
+    /// How much the finish moved, if the task is in both plans
+    pub delta: Option<Finish>,
+    pub changes: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Comparison {
+    pub before_name: String,
+    pub after_name: String,
+    pub before: Option<Finish>,
+    pub after: Option<Finish>,
+    pub delta: Option<Finish>,
+    pub project_changes: Vec<String>,
+    pub tasks: Vec<TaskComparison>,
+    pub warnings: Vec<String>,
+}
+
+/// Forecast two plans and compare when each task finishes
+pub fn compare_plans(before: &Plan, after: &Plan) -> Comparison {
+    let mut warnings = vec![];
+    let before_finishes = forecast_finishes(before, &mut warnings);
+    let after_finishes = forecast_finishes(after, &mut warnings);
+
+    let before_tasks: HashMap<&str, &Task> =
+        HashMap::from_iter(before.tasks.iter().map(|t| (t.uid.as_str(), t)));
+    let after_tasks: HashMap<&str, &Task> =
+        HashMap::from_iter(after.tasks.iter().map(|t| (t.uid.as_str(), t)));
+
+    // Names of everyone in either plan, to describe assignments
+    let names: HashMap<&str, &str> = HashMap::from_iter(
+        before
+            .project
+            .people
+            .iter()
+            .chain(&after.project.people)
+            .map(|p| (p.uid.as_str(), p.name.as_str())),
+    );
+
+    let tasks = before_tasks
+        .keys()
+        .chain(after_tasks.keys())
+        .unique()
+        .map(|uid| {
+            let b = before_tasks.get(uid).copied();
+            let a = after_tasks.get(uid).copied();
+            let latest = a.or(b).unwrap();
+            let before = before_finishes.get(*uid).copied();
+            let after = after_finishes.get(*uid).copied();
+            TaskComparison {
+                id: latest.id.clone(),
+                title: latest.title.clone(),
+                before,
+                after,
+                delta: finish_delta(before, after),
+                changes: describe_task_changes(b, a, &before_tasks, &after_tasks, &names),
+            }
+        })
+        .sorted_by(|a, b| human_sort::compare(&a.id, &b.id))
+        .collect();
+
+    let before_total = total_finish(&before_finishes);
+    let after_total = total_finish(&after_finishes);
+    Comparison {
+        before_name: before.name.clone(),
+        after_name: after.name.clone(),
+        before: before_total,
+        after: after_total,
+        delta: finish_delta(before_total, after_total),
+        project_changes: describe_project_changes(&before.project, &after.project),
+        tasks,
+        warnings,
+    }
+}
+
+fn forecast_finishes(plan: &Plan, warnings: &mut Vec<String>) -> HashMap<String, Finish> {
+    let sorted_tasks = roughly_sort_tasks(plan.tasks.iter());
+    if !sorted_tasks.cycles.is_empty() {
+        warnings.push(format!(
+            "{} has a dependency cycle and can't be forecast",
+            plan.name
+        ));
+        return Default::default();
+    }
+
+    let mut project = plan.project.clone();
+    share_out(&mut project, &plan.allocations);
+    let Ok(rs) = simulate_project(&project, &plan.tasks, &plan.upstream, plan.today) else {
+        warnings.push(format!(
+            "{} has a dependency cycle with another project and can't be forecast",
+            plan.name
+        ));
+        return Default::default();
+    };
+    let tasks = HashMap::from_iter(plan.tasks.iter().map(|t| (t.uid.as_str(), t)));
+    for warning in staffing_warnings(&tasks, &rs.unstaffed) {
+        warnings.push(format!("{}: {}", plan.name, warning));
+    }
+    let calendar = &plan.project.calendar;
+    rs.task_stats
+        .iter()
+        .filter_map(|(uid, stats)| {
+            let p50 = stats.end.quantile(0.5)?;
+            let p90 = stats.end.quantile(0.9)?;
+            Some((
+                uid.clone(),
+                Finish {
+                    p50,
+                    p90,
+                    p50_date: calendar.finish_date(p50),
+                    p90_date: calendar.finish_date(p90),
+                },
+            ))
+        })
+        .collect()
+}
+
+/// The project finishes when its last task finishes
+fn total_finish(finishes: &HashMap<String, Finish>) -> Option<Finish> {
+    finishes.values().copied().reduce(|a, b| Finish {
+        p50: a.p50.max(b.p50),
+        p90: a.p90.max(b.p90),
+        p50_date: a.p50_date.max(b.p50_date),
+        p90_date: a.p90_date.max(b.p90_date),
+    })
+}
+
+/// In working days, the dates are left out
+fn finish_delta(before: Option<Finish>, after: Option<Finish>) -> Option<Finish> {
+    before.zip(after).map(|(b, a)| Finish {
+        p50: a.p50 - b.p50,
+        p90: a.p90 - b.p90,
+        p50_date: None,
+        p90_date: None,
+    })
+}
+
+fn describe_project_changes(before: &Project, after: &Project) -> Vec<String> {
+    let mut ret = vec![];
+    let before_people: HashMap<&str, &Person> =
+        HashMap::from_iter(before.people.iter().map(|p| (p.uid.as_str(), p)));
+    for person in &after.people {
+        match before_people.get(person.uid.as_str()) {
+            None => ret.push(format!("{} joined", person.name)),
+            Some(p) if p.capacity != person.capacity => ret.push(format!(
+                "{} capacity {} → {}",
+                person.name, p.capacity, person.capacity
+            )),
+            Some(p) if p.start != person.start || p.vacations != person.vacations => {
+                ret.push(format!("{} availability changed", person.name))
+            }
+            Some(p) if p.pool != person.pool => ret.push(match person.pool {
+                Some(_) => format!("{} is shared with other projects", person.name),
+                None => format!("{} only works on this project", person.name),
+            }),
+            Some(_) => {}
+        }
+    }
+    let after_people: HashSet<&str> = after.people.iter().map(|p| p.uid.as_str()).collect();
+    for person in &before.people {
+        if !after_people.contains(person.uid.as_str()) {
+            ret.push(format!("{} left", person.name));
+        }
+    }
+    if before.seed != after.seed {
+        ret.push(format!("seed {} → {}", before.seed, after.seed));
+    }
+    if before.calendar.start != after.calendar.start {
+        ret.push(format!(
+            "start {} → {}",
+            fmt_opt(before.calendar.start),
+            fmt_opt(after.calendar.start)
+        ));
+    }
+    if before.calendar.working_days != after.calendar.working_days
+        || before.calendar.holidays != after.calendar.holidays
+    {
+        ret.push("working days changed".to_owned());
+    }
+    if before.iterations != after.iterations {
+        ret.push(format!(
+            "iterations {} → {}",
+            before.iterations, after.iterations
+        ));
+    }
+    if before.tolerance != after.tolerance {
+        ret.push(format!(
+            "tolerance {} → {}",
+            fmt_opt(before.tolerance),
+            fmt_opt(after.tolerance)
+        ));
+    }
+    if before.confidence != after.confidence {
+        ret.push(format!(
+            "confidence {:.0}% → {:.0}%",
+            before.confidence * 100.0,
+            after.confidence * 100.0
+        ));
+    }
+    if before.scheduling != after.scheduling {
+        ret.push(format!(
+            "scheduling {} → {}",
+            before.scheduling, after.scheduling
+        ));
+    }
+    if before.efficiency_loss != after.efficiency_loss {
+        ret.push(format!(
+            "efficiency loss {:.0}% → {:.0}%",
+            before.efficiency_loss * 100.0,
+            after.efficiency_loss * 100.0
+        ));
+    }
+    ret
+}
+
+fn describe_task_changes(
+    before: Option<&Task>,
+    after: Option<&Task>,
+    before_tasks: &HashMap<&str, &Task>,
+    after_tasks: &HashMap<&str, &Task>,
+    names: &HashMap<&str, &str>,
+) -> Vec<String> {
+    let (before, after) = match (before, after) {
+        (Some(b), Some(a)) => (b, a),
+        (None, _) => return vec!["added".to_owned()],
+        (_, None) => return vec!["removed".to_owned()],
+    };
+
+    let mut ret = vec![];
+    if before.r#type != after.r#type {
+        ret.push(format!("type {:?} → {:?}", before.r#type, after.r#type).to_lowercase());
+    }
+    if before.estimate != after.estimate {
+        ret.push(format!(
+            "re-estimated {} → {}",
+            fmt_opt(before.estimate),
+            fmt_opt(after.estimate)
+        ));
+    }
+    if before.distribution != after.distribution {
+        ret.push(
+            format!(
+                "distribution {:?} → {:?}",
+                before.distribution, after.distribution
+            )
+            .to_lowercase(),
+        );
+    }
+    if (before.optimistic, before.pessimistic) != (after.optimistic, after.pessimistic) {
+        ret.push(format!(
+            "range {}–{} → {}–{}",
+            fmt_opt(before.optimistic),
+            fmt_opt(before.pessimistic),
+            fmt_opt(after.optimistic),
+            fmt_opt(after.pessimistic)
+        ));
+    }
+    if before.sigma != after.sigma {
+        ret.push(format!(
+            "sigma {} → {}",
+            fmt_opt(before.sigma),
+            fmt_opt(after.sigma)
+        ));
+    }
+    if before.risk != after.risk {
+        ret.push(
+            format!(
+                "risk {} → {}",
+                fmt_opt(before.risk.map(|r| format!("{:?}", r))),
+                fmt_opt(after.risk.map(|r| format!("{:?}", r)))
+            )
+            .to_lowercase(),
+        );
+    }
+
+    if before.status != after.status {
+        let status = |s: TaskStatus| match s {
+            TaskStatus::Todo => "to do",
+            TaskStatus::InProgress => "in progress",
+            TaskStatus::Done => "done",
+        };
+        ret.push(format!(
+            "{} → {}",
+            status(before.status),
+            status(after.status)
+        ));
+    }
+    if before.remaining != after.remaining {
+        ret.push(format!(
+            "remaining {} → {}",
+            fmt_opt(before.remaining),
+            fmt_opt(after.remaining)
+        ));
+    }
+    if before.deadline != after.deadline {
+        ret.push(format!(
+            "deadline {} → {}",
+            fmt_opt(before.deadline),
+            fmt_opt(after.deadline)
+        ));
+    }
+    if before.priority != after.priority {
+        ret.push(format!(
+            "priority {} → {}",
+            fmt_opt(before.priority),
+            fmt_opt(after.priority)
+        ));
+    }
+    if before.max_people != after.max_people {
+        ret.push(format!(
+            "max people {} → {}",
+            before.max_people.unwrap_or(1),
+            after.max_people.unwrap_or(1)
+        ));
+    }
+    if before.skills != after.skills {
+        ret.push(format!(
+            "skills {} → {}",
+            fmt_skills(&before.skills),
+            fmt_skills(&after.skills)
+        ));
+    }
+    if before.assignee != after.assignee {
+        let name = |a: &Option<String>| match a {
+            Some(uid) => names.get(uid.as_str()).copied().unwrap_or(uid).to_owned(),
+            None => "anyone".to_owned(),
+        };
+        ret.push(format!(
+            "assigned {} → {}",
+            name(&before.assignee),
+            name(&after.assignee)
+        ));
+    }
+
+    let dep_id = |uid: &String| {
+        after_tasks
+            .get(uid.as_str())
+            .or(before_tasks.get(uid.as_str()))
+            .map(|t| t.id.clone())
+            .unwrap_or_else(|| uid.clone())
+    };
+    if before.parent != after.parent {
+        let epic = |uid: &Option<String>| uid.as_ref().map_or("-".to_owned(), dep_id);
+        ret.push(format!(
+            "epic {} → {}",
+            epic(&before.parent),
+            epic(&after.parent)
+        ));
+    }
+    // Snapshots only hold their own project, so tasks of other projects go by uid
+    let dep = |uid: &String, dependency: &Dependency| {
+        let id = match dependency.project {
+            Some(_) => format!("{} of another project", dep_id(uid)),
+            None => dep_id(uid),
+        };
+        let plain = Dependency {
+            project: None,
+            ..dependency.clone()
+        };
+        match plain == Dependency::default() {
+            true => id,
+            false => format!("{} ({})", id, dependency),
+        }
+    };
+    for (uid, dependency) in &after.dependencies {
+        match before.dependencies.get(uid) {
+            None => ret.push(format!("now depends on {}", dep(uid, dependency))),
+            Some(old) if old != dependency => ret.push(format!(
+                "dependency on {} {} → {}",
+                dep_id(uid),
+                old,
+                dependency
+            )),
+            Some(_) => {}
+        }
+    }
+    for (uid, dependency) in &before.dependencies {
+        if !after.dependencies.contains_key(uid) {
+            ret.push(format!("no longer depends on {}", dep(uid, dependency)));
+        }
+    }
+    ret
+}
+
+fn fmt_skills(skills: &BTreeSet<String>) -> String {
+    match skills.is_empty() {
+        true => "-".to_owned(),
+        false => skills.iter().join(" "),
+    }
+}
+
+fn fmt_opt<A: ToString>(x: Option<A>) -> String {
+    x.map(|x| x.to_string()).unwrap_or_else(|| "-".to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        datamodel::PoolMember,
+        hstable::HSTable,
+        portfolio::{project_plan, ProjectPlan},
+    };
+
+    #[test]
+    fn current_plan_forecasts_like_the_forecast_tab() {
+        // Ann works on both projects, and p waits for a task of q
+        let projects = HSTable::from_iter(["p", "q"].map(|uid| Project {
+            uid: uid.to_owned(),
+            name: uid.to_owned(),
+            people: vec![Person {
+                uid: "ann".to_owned(),
+                name: "Ann".to_owned(),
+                pool: Some("ann".to_owned()),
+                ..Default::default()
+            }],
+            seed: 1,
+            iterations: 200,
+            ..Default::default()
+        }));
+        let task = |project_uid: &str, uid: &str| Task {
+            project_uid: project_uid.to_owned(),
+            uid: uid.to_owned(),
+            id: uid.to_owned(),
+            estimate: Some(5.0),
+            ..Default::default()
+        };
+        let mut b = task("p", "b");
+        b.dependencies.insert(
+            "a".to_owned(),
+            Dependency {
+                project: Some("q".to_owned()),
+                ..Default::default()
+            },
+        );
+        let tasks = HSTable::from_iter([task("q", "a"), b]);
+        let pool = [PoolMember {
+            uid: "ann".to_owned(),
+            name: "Ann".to_owned(),
+            capacity: 1.0,
+        }];
+        let today = NaiveDate::from_ymd_opt(2026, 1, 5).unwrap();
+        let planned = projects.get1("p").unwrap().clone();
+
+        // The way the Forecast tab does it
+        let ProjectPlan {
+            project,
+            tasks,
+            upstream,
+            allocations,
+        } = project_plan(planned.clone(), &projects, &tasks, &pool);
+        let rs = simulate_project(&project, &tasks, &upstream, today).unwrap();
+        let p50 = rs.task_stats["b"].end.quantile(0.5).unwrap();
+
+        let current = Plan {
+            name: "Current plan".to_owned(),
+            project: planned,
+            tasks,
+            today,
+            upstream,
+            allocations,
+        };
+        let comparison = compare_plans(&current, &current);
+        assert_eq!(comparison.after.unwrap().p50, p50);
+        assert!(comparison.project_changes.is_empty());
+
+        // Without waiting for q, and with all of Ann's time, it would finish sooner
+        let alone = Plan {
+            upstream: vec![],
+            allocations: vec![],
+            ..current
+        };
+        assert!(compare_plans(&alone, &alone).after.unwrap().p50 < p50);
+    }
+}
@@ -1,12 +1,21 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fmt,
+    str::FromStr,
+};
 
+use chrono::{DateTime, NaiveDate, Utc};
 use itertools::Itertools;
 use petgraph::{algo::tarjan_scc, graph::DiGraph, matrix_graph::NodeIndex, Graph};
 use serde::{Deserialize, Serialize};
 
-use crate::hstable::{HashColl, Hashable, SortColl, Sortable};
+use crate::{
+    calendar::Calendar,
+    hstable::{HashColl, Hashable, SortColl, Sortable},
+    ids::unique_id,
+};
 
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
 #[serde(default)]
 pub struct Task {
     pub project_uid: String,
@@ -14,10 +23,37 @@ pub struct Task {
     pub r#type: TaskType,
     pub id: String,
     pub title: String,
+    /// Days of work, the most likely value for a three-point estimate
     pub estimate: Option<f64>,
     pub risk: Option<Risk>,
-    pub dependencies: HashSet<String>,
-    // TODO: Max parallellization, specialization
+    pub distribution: Distribution,
+    /// Lower bound of a three-point estimate
+    pub optimistic: Option<f64>,
+    /// Upper bound of a three-point estimate
+    pub pessimistic: Option<f64>,
+    /// Spread of a log-normal estimate, instead of the one that goes with the risk
+    pub sigma: Option<f64>,
+    /// By uid of the task depended on
+    pub dependencies: HashMap<String, Dependency>,
+    /// Only people with all of these skills can work on the task
+    pub skills: BTreeSet<String>,
+    /// Uid of the one person who works on the task, regardless of skills
+    pub assignee: Option<String>,
+    pub status: TaskStatus,
+    /// When work actually started
+    pub started: Option<NaiveDate>,
+    /// When the task was actually done
+    pub finished: Option<NaiveDate>,
+    /// Days of work left on a task in progress, instead of the estimate
+    pub remaining: Option<f64>,
+    /// When a milestone has to be reached
+    pub deadline: Option<Deadline>,
+    /// Uid of the epic the task is part of
+    pub parent: Option<String>,
+    /// 1 goes first when scheduling by priority, subtasks without one take their epic's
+    pub priority: Option<u32>,
+    /// How many people can work on the task at once, one when left out
+    pub max_people: Option<u32>,
 }
 
 impl Hashable for Task {
@@ -37,12 +73,25 @@ impl Sortable for Task {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(default)]
 pub struct Project {
     pub uid: String,
     pub name: String,
-    pub people: u32,
+    pub people: Vec<Person>,
+    /// Seed for the forecast, so the same plan always gives the same forecast
+    pub seed: u64,
+    /// How many times to simulate the project, the maximum when converging
+    pub iterations: u32,
+    /// Stop simulating once the estimates move less than this fraction between rounds
+    pub tolerance: Option<f64>,
+    /// Milestones less likely than this to make their deadline are flagged
+    pub confidence: f64,
+    /// Which of the tasks that are ready to start gets picked up first
+    pub scheduling: Scheduling,
+    /// Work added for every extra person on a task, as a fraction of the task
+    pub efficiency_loss: f64,
+    pub calendar: Calendar,
 }
 
 impl Default for Project {
@@ -50,7 +99,143 @@ impl Default for Project {
         Self {
             uid: Default::default(),
             name: Default::default(),
-            people: 1,
+            people: Person::team(1),
+            seed: 0,
+            iterations: 1000,
+            tolerance: None,
+            confidence: 0.8,
+            scheduling: Default::default(),
+            efficiency_loss: 0.1,
+            calendar: Default::default(),
+        }
+    }
+}
+
+/// Someone working on a project
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct Person {
+    pub uid: String,
+    pub name: String,
+    /// Fraction of a full-time week spent on the project, 0.5 for half time
+    pub capacity: f64,
+    pub skills: BTreeSet<String>,
+    /// First day on the project, only used when the project has a start date
+    pub start: Option<NaiveDate>,
+    pub vacations: Vec<Vacation>,
+    /// Uid of the `PoolMember` this is, whose time is shared with other projects
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pool: Option<String>,
+}
+
+impl Default for Person {
+    fn default() -> Self {
+        Self {
+            uid: Default::default(),
+            name: Default::default(),
+            capacity: 1.0,
+            skills: Default::default(),
+            start: None,
+            vacations: vec![],
+            pool: None,
+        }
+    }
+}
+
+/// Someone in the organisation, who can be on several projects at once
+///
+/// Each project they're on gets the share of their time that is the capacity
+/// of their `Person` there.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct PoolMember {
+    pub uid: String,
+    pub name: String,
+    /// Fraction of a full-time week they work, to be split between projects
+    pub capacity: f64,
+}
+
+impl Default for PoolMember {
+    fn default() -> Self {
+        Self {
+            uid: Default::default(),
+            name: Default::default(),
+            capacity: 1.0,
+        }
+    }
+}
+
+impl Person {
+    /// Whether this person can work on the task
+    pub fn qualifies_for(&self, task: &Task) -> bool {
+        match &task.assignee {
+            Some(assignee) => *assignee == self.uid,
+            None => task.skills.is_subset(&self.skills),
+        }
+    }
+
+    /// `n` anonymous full-time people, like projects had before people had names
+    pub fn team(n: u32) -> Vec<Person> {
+        (1..=n.max(1))
+            .map(|i| Person {
+                uid: format!("person-{}", i),
+                name: format!("Person {}", i),
+                ..Default::default()
+            })
+            .collect()
+    }
+}
+
+/// Read skills separated by spaces or commas, skills are lowercase
+pub fn parse_skills(skills: &str) -> BTreeSet<String> {
+    skills
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_lowercase())
+        .collect()
+}
+
+/// Days off, `start` to `end` inclusive
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Vacation {
+    pub start: NaiveDate,
+    pub end: NaiveDate,
+}
+
+/// The API still accepts how many people work on a project instead of the people
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum PeopleOrCount {
+    People(Vec<Person>),
+    Count(u32),
+}
+
+impl From<PeopleOrCount> for Vec<Person> {
+    fn from(people: PeopleOrCount) -> Self {
+        match people {
+            PeopleOrCount::People(people) => people,
+            PeopleOrCount::Count(n) => Person::team(n),
+        }
+    }
+}
+
+impl Project {
+    /// Give everyone a uid and a name, and keep at least one person on the project
+    pub fn ensure_people(&mut self) {
+        if self.people.is_empty() {
+            self.people = Person::team(1);
+        }
+        for (i, person) in self.people.iter_mut().enumerate() {
+            if person.uid.is_empty() {
+                person.uid = unique_id();
+            }
+            if person.name.trim().is_empty() {
+                person.name = format!("Person {}", i + 1);
+            }
+            if !person.capacity.is_finite() || person.capacity < 0.0 {
+                person.capacity = 0.0;
+            }
+            person.vacations.retain(|v| v.start <= v.end);
         }
     }
 }
@@ -64,6 +249,34 @@ impl Hashable for Project {
     }
 }
 
+/// An immutable copy of a project and its tasks at some point in time
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Snapshot {
+    pub project_uid: String,
+    pub uid: String,
+    pub name: String,
+    pub created: DateTime<Utc>,
+    pub project: Project,
+    pub tasks: Vec<Task>,
+}
+
+impl Hashable for Snapshot {
+    type Coll = SortColl<Snapshot>;
+    type HashKey = String;
+
+    fn hash_key(&self) -> &String {
+        &self.project_uid
+    }
+}
+
+impl Sortable for Snapshot {
+    type SortKey = String;
+
+    fn sort_key(&self) -> &String {
+        &self.uid
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Default, FromFormField, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum TaskType {
@@ -80,7 +293,228 @@ impl Task {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, FromFormField, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, FromFormField, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TaskStatus {
+    #[default]
+    Todo,
+    #[field(value = "in-progress")]
+    InProgress,
+    Done,
+}
+
+/// How the duration of a task is sampled
+///
+/// Three-point estimates fall back to log-normal when their bounds are missing
+/// or don't surround the estimate.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, FromFormField, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Distribution {
+    /// Long-tailed around the estimate, with a spread from the risk or sigma
+    #[default]
+    LogNormal,
+    Pert,
+    Triangular,
+}
+
+/// In which order people pick up the tasks that are ready to start
+///
+/// Tasks in progress always go first, except when picking at random.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, FromFormField, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Scheduling {
+    /// Any task that is ready
+    #[default]
+    Random,
+    /// The most urgent first, tasks without a priority last
+    Priority,
+    /// The task with the most expected work waiting on it first
+    #[field(value = "longest-path")]
+    LongestPath,
+    /// The task expected to take the least time first
+    #[field(value = "shortest-task")]
+    ShortestTask,
+}
+
+impl fmt::Display for Scheduling {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Scheduling::Random => "random",
+            Scheduling::Priority => "priority",
+            Scheduling::LongestPath => "longest-path",
+            Scheduling::ShortestTask => "shortest-task",
+        })
+    }
+}
+
+impl FromStr for Scheduling {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "random" => Ok(Scheduling::Random),
+            "priority" => Ok(Scheduling::Priority),
+            "longest-path" => Ok(Scheduling::LongestPath),
+            "shortest-task" => Ok(Scheduling::ShortestTask),
+            _ => Err(format!(
+                "{} is not a scheduling policy, use random, priority, longest-path or shortest-task",
+                s
+            )),
+        }
+    }
+}
+
+/// How a task waits for one of the tasks it depends on
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct Dependency {
+    pub kind: DependencyKind,
+    /// Working days to wait on top, negative to overlap
+    pub lag: f64,
+    /// Uid of the project of the task depended on, when it is another one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub project: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DependencyKind {
+    /// Start once the other task finishes
+    #[default]
+    #[serde(rename = "fs")]
+    FinishToStart,
+    /// Start once the other task starts
+    #[serde(rename = "ss")]
+    StartToStart,
+    /// Finish once the other task finishes
+    #[serde(rename = "ff")]
+    FinishToFinish,
+}
+
+/// Written like `SS+2`, a plain finish-to-start dependency is `FS`
+impl fmt::Display for Dependency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            DependencyKind::FinishToStart => "FS",
+            DependencyKind::StartToStart => "SS",
+            DependencyKind::FinishToFinish => "FF",
+        };
+        if self.lag == 0.0 {
+            write!(f, "{}", kind)
+        } else {
+            write!(f, "{}{:+}", kind, self.lag)
+        }
+    }
+}
+
+/// Reads `FS`, `ss+2`, `FF-1` or just a lag like `+2`, which is finish-to-start
+impl FromStr for Dependency {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let error = || {
+            format!(
+                "{} is not a dependency, use FS, SS or FF and a lag like SS+2",
+                s
+            )
+        };
+        let split = s.find(['+', '-']).unwrap_or(s.len());
+        let kind = match s[..split].trim().to_uppercase().as_str() {
+            "" | "FS" => DependencyKind::FinishToStart,
+            "SS" => DependencyKind::StartToStart,
+            "FF" => DependencyKind::FinishToFinish,
+            _ => return Err(error()),
+        };
+        let lag = match s[split..].trim() {
+            "" => 0.0,
+            lag => lag
+                .parse::<f64>()
+                .ok()
+                .filter(|l| l.is_finite())
+                .ok_or_else(error)?,
+        };
+        Ok(Dependency {
+            kind,
+            lag,
+            project: None,
+        })
+    }
+}
+
+/// Split `ID:SS+2` into the ID and the dependency, a plain ID is finish-to-start
+pub fn parse_dependency(s: &str) -> Result<(&str, Dependency), String> {
+    match s.split_once(':') {
+        Some((id, dependency)) => Ok((id.trim(), dependency.parse()?)),
+        None => Ok((s.trim(), Dependency::default())),
+    }
+}
+
+/// The API still accepts a list of uids, which all mean finish-to-start
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum DependenciesOrList {
+    Dependencies(HashMap<String, Dependency>),
+    List(HashSet<String>),
+}
+
+impl From<DependenciesOrList> for HashMap<String, Dependency> {
+    fn from(dependencies: DependenciesOrList) -> Self {
+        match dependencies {
+            DependenciesOrList::Dependencies(dependencies) => dependencies,
+            DependenciesOrList::List(uids) => uids
+                .into_iter()
+                .map(|uid| (uid, Dependency::default()))
+                .collect(),
+        }
+    }
+}
+
+/// A date, or a number of working days into the project when it has no dates
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(untagged)]
+pub enum Deadline {
+    Date(NaiveDate),
+    Day(f64),
+}
+
+impl Deadline {
+    /// Working days into the project the deadline falls, the end of that day for a date
+    pub fn offset(&self, calendar: &Calendar) -> Option<f64> {
+        match self {
+            Deadline::Date(date) => calendar.offset(date.succ_opt()?),
+            Deadline::Day(day) => Some(*day),
+        }
+    }
+}
+
+impl fmt::Display for Deadline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Deadline::Date(date) => write!(f, "{}", date),
+            Deadline::Day(day) => write!(f, "{}", day),
+        }
+    }
+}
+
+impl FromStr for Deadline {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Ok(date) = s.parse() {
+            return Ok(Deadline::Date(date));
+        }
+        match s.parse::<f64>() {
+            Ok(day) if day.is_finite() && day >= 0.0 => Ok(Deadline::Day(day)),
+            _ => Err(format!(
+                "{} is not a date or a day, use YYYY-MM-DD or a number",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, FromFormField, Default, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum Risk {
     Low,
@@ -97,11 +531,40 @@ pub struct TaskUpdate {
     pub title: Option<String>,
     pub estimate: Option<Option<f64>>,
     pub risk: Option<Option<Risk>>,
-    pub add_dependencies: Vec<String>,
+    pub distribution: Option<Distribution>,
+    pub optimistic: Option<Option<f64>>,
+    pub pessimistic: Option<Option<f64>>,
+    pub sigma: Option<Option<f64>>,
+    /// Added, or changed when the task already depends on it
+    pub add_dependencies: Vec<(String, Dependency)>,
     pub remove_dependencies: Vec<String>,
+    pub skills: Option<BTreeSet<String>>,
+    pub assignee: Option<Option<String>>,
+    pub status: Option<TaskStatus>,
+    pub started: Option<Option<NaiveDate>>,
+    pub finished: Option<Option<NaiveDate>>,
+    pub remaining: Option<Option<f64>>,
+    pub deadline: Option<Option<Deadline>>,
+    pub parent: Option<Option<String>>,
+    pub priority: Option<Option<u32>>,
+    pub max_people: Option<Option<u32>>,
 }
 
 impl TaskUpdate {
+    /// Apply the update to an existing task, or create a new task if there is none
+    ///
+    /// `task_count` is the number of tasks already in the project, used to pick a default ID.
+    pub fn upsert(self, existing: Option<&Task>, task_count: usize) -> Task {
+        if let Some(existing) = existing {
+            self.apply(existing)
+        } else {
+            let mut task = self.apply(&Default::default());
+            // Make sure every task has an ID
+            task.ensure_defaults(task_count);
+            task
+        }
+    }
+
     pub fn apply(self, task: &Task) -> Task {
         Task {
             project_uid: self.project_uid.clone(),
@@ -111,11 +574,15 @@ impl TaskUpdate {
             title: self.title.unwrap_or(task.title.clone()),
             estimate: self.estimate.unwrap_or(task.estimate),
             risk: self.risk.unwrap_or(task.risk),
+            distribution: self.distribution.unwrap_or(task.distribution),
+            optimistic: self.optimistic.unwrap_or(task.optimistic),
+            pessimistic: self.pessimistic.unwrap_or(task.pessimistic),
+            sigma: self.sigma.unwrap_or(task.sigma),
             dependencies: {
                 let mut deps = task.dependencies.clone();
-                for d in self.add_dependencies {
+                for (d, dependency) in self.add_dependencies {
                     if d != self.uid {
-                        deps.insert(d);
+                        deps.insert(d, dependency);
                     }
                 }
                 for d in self.remove_dependencies {
@@ -123,23 +590,111 @@ impl TaskUpdate {
                 }
                 deps
             },
+            skills: self.skills.unwrap_or(task.skills.clone()),
+            assignee: self.assignee.unwrap_or(task.assignee.clone()),
+            status: self.status.unwrap_or(task.status),
+            started: self.started.unwrap_or(task.started),
+            finished: self.finished.unwrap_or(task.finished),
+            remaining: self.remaining.unwrap_or(task.remaining),
+            deadline: self.deadline.unwrap_or(task.deadline),
+            parent: match self.parent {
+                Some(parent) => parent.filter(|p| *p != self.uid),
+                None => task.parent.clone(),
+            },
+            priority: self.priority.unwrap_or(task.priority),
+            max_people: self.max_people.unwrap_or(task.max_people),
+        }
+    }
+}
+
+/// The epic of a task, the epic of that epic and so on, stopping short of a loop
+pub fn ancestors<'a>(by_uid: &HashMap<&str, &'a Task>, task: &'a Task) -> Vec<&'a str> {
+    let mut ret: Vec<&str> = vec![];
+    let mut next = task.parent.as_deref();
+    while let Some(parent) = next.and_then(|p| by_uid.get(p)) {
+        if parent.uid == task.uid || ret.contains(&parent.uid.as_str()) {
+            break;
         }
+        ret.push(&parent.uid);
+        next = parent.parent.as_deref();
     }
+    ret
+}
+
+/// Whether the task is `epic` or one of its subtasks, at any depth
+pub fn is_part_of(by_uid: &HashMap<&str, &Task>, task: &str, epic: &str) -> bool {
+    task == epic
+        || by_uid
+            .get(task)
+            .is_some_and(|t| ancestors(by_uid, t).contains(&epic))
+}
+
+/// The subtasks of every epic, by uid
+pub fn children<'a>(by_uid: &HashMap<&str, &'a Task>) -> HashMap<&'a str, Vec<&'a str>> {
+    let mut ret: HashMap<&str, Vec<&str>> = HashMap::new();
+    for task in by_uid.values() {
+        if let Some(parent) = task.parent.as_deref().and_then(|p| by_uid.get(p)) {
+            if parent.uid != task.uid {
+                ret.entry(&parent.uid).or_default().push(&task.uid);
+            }
+        }
+    }
+    ret
+}
+
+/// What a task waits for, its own dependencies and those of its epics
+///
+/// A subtask can't start before its epic could. Tasks of other projects are
+/// left out, see `portfolio::qualified` to forecast them together.
+pub fn inherited_dependencies<'a>(
+    by_uid: &HashMap<&str, &'a Task>,
+    task: &'a Task,
+) -> HashMap<&'a str, Dependency> {
+    let mut ret = HashMap::new();
+    for t in ancestors(by_uid, task)
+        .into_iter()
+        .rev()
+        .map(|uid| by_uid[uid])
+        .chain([task])
+    {
+        for (d, dependency) in &t.dependencies {
+            if dependency
+                .project
+                .as_ref()
+                .is_none_or(|p| *p == t.project_uid)
+            {
+                ret.insert(d.as_str(), dependency.clone());
+            }
+        }
+    }
+    ret.remove(task.uid.as_str());
+    ret
 }
 
 /// Make a dependency graph from a set of tasks
-fn make_dependency_graph<'a>(tasks: impl Iterator<Item = &'a Task> + Clone) -> Graph<Task, ()> {
-    let tasks_copy = tasks.clone();
-    let mut g = DiGraph::<Task, (), u32>::new();
-    let nodes: HashMap<String, NodeIndex<u32>> =
-        HashMap::from_iter(tasks.map(|t| (t.uid.clone(), g.add_node(t.clone()))));
-    for task in tasks_copy {
+///
+/// Epics depend on their subtasks, and subtasks on whatever their epics depend on.
+fn make_dependency_graph<'a>(
+    tasks: impl Iterator<Item = &'a Task> + Clone,
+) -> Graph<Task, Dependency> {
+    let by_uid: HashMap<&str, &Task> =
+        HashMap::from_iter(tasks.clone().map(|t| (t.uid.as_str(), t)));
+    let mut g = DiGraph::<Task, Dependency, u32>::new();
+    let nodes: HashMap<String, NodeIndex<u32>> = HashMap::from_iter(
+        tasks
+            .clone()
+            .map(|t| (t.uid.clone(), g.add_node(t.clone()))),
+    );
+    for task in tasks {
         if let Some(task_node) = nodes.get(&task.uid) {
-            for d in &task.dependencies {
+            for (d, dependency) in inherited_dependencies(&by_uid, task) {
                 if let Some(dep_node) = nodes.get(d) {
-                    g.add_edge(*task_node, *dep_node, ());
+                    g.add_edge(*task_node, *dep_node, dependency);
                 }
             }
+            if let Some(parent_node) = task.parent.as_ref().and_then(|p| nodes.get(p)) {
+                g.add_edge(*parent_node, *task_node, Dependency::default());
+            }
         }
     }
     g
@@ -177,3 +732,54 @@ pub struct SortedTasks {
     pub sorted_tasks: Vec<Task>,
     pub cycles: Vec<Vec<String>>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dependencies_read_what_they_write() {
+        for kind in [
+            DependencyKind::FinishToStart,
+            DependencyKind::StartToStart,
+            DependencyKind::FinishToFinish,
+        ] {
+            for lag in [0.0, 2.0, -1.5] {
+                let dependency = Dependency {
+                    kind,
+                    lag,
+                    project: None,
+                };
+                assert_eq!(dependency.to_string().parse(), Ok(dependency));
+            }
+        }
+        assert_eq!(
+            parse_dependency(" A : ss+2 "),
+            Ok((
+                "A",
+                Dependency {
+                    kind: DependencyKind::StartToStart,
+                    lag: 2.0,
+                    project: None,
+                }
+            ))
+        );
+        assert_eq!(parse_dependency("A"), Ok(("A", Dependency::default())));
+        assert!(parse_dependency("A:XX").is_err());
+        assert!(parse_dependency("A:FS+inf").is_err());
+    }
+
+    #[test]
+    fn deadlines_read_what_they_write() {
+        for deadline in [
+            Deadline::Date(NaiveDate::from_ymd_opt(2026, 3, 31).unwrap()),
+            Deadline::Day(0.0),
+            Deadline::Day(12.5),
+        ] {
+            assert_eq!(deadline.to_string().parse(), Ok(deadline));
+        }
+        assert!("-1".parse::<Deadline>().is_err());
+        assert!("2026-02-30".parse::<Deadline>().is_err());
+        assert!("soon".parse::<Deadline>().is_err());
+    }
+}
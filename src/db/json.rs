@@ -0,0 +1,418 @@
+use std::{
+    fs,
+    io::{self, BufReader, BufWriter},
+    sync::RwLock,
+};
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use fs2::FileExt;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    datamodel::{PoolMember, Project, Snapshot, Task, TaskUpdate},
+    history::{self, Change, ChangeLog, Event, LogEntry},
+    hstable::HSTable,
+    ids::unique_id,
+};
+
+use super::{migrations, Storage};
+
+/// Stores the entire database in a single JSON file
+///
+/// Every load/modify/save cycle holds a lock on a `.lock` file next to the
+/// database, so concurrent requests and processes don't overwrite each other's
+/// changes. Saving writes to a temporary file first and then renames it over
+/// the database, so a crash never leaves a half-written file behind.
+///
+/// In cached mode the database is only read from disk once, and kept in
+/// memory afterwards. Every change is still written through to disk before
+/// it becomes visible. This assumes no other process writes to the file.
+pub struct JsonStorage {
+    filename: String,
+    cache: Option<RwLock<FullDatabase>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+struct FullDatabase {
+    pub version: u32,
+    pub tasks: HSTable<Task>,
+    pub projects: HSTable<Project>,
+    pub history: HSTable<LogEntry>,
+    pub snapshots: HSTable<Snapshot>,
+    pub pool: Vec<PoolMember>,
+}
+
+impl Default for FullDatabase {
+    fn default() -> Self {
+        Self {
+            version: migrations::CURRENT_VERSION,
+            tasks: Default::default(),
+            projects: Default::default(),
+            history: Default::default(),
+            snapshots: Default::default(),
+            pool: vec![],
+        }
+    }
+}
+
+impl JsonStorage {
+    /// Open the database, upgrading it to the current version if necessary
+    pub fn open(filename: String) -> Result<Self> {
+        let ret = Self {
+            filename,
+            cache: None,
+        };
+        ret.upgrade()?;
+        Ok(ret)
+    }
+
+    pub fn cached(filename: String) -> Result<Self> {
+        let mut ret = Self::open(filename)?;
+        let db = {
+            let _lock = ret.lock(false)?;
+            ret.load()?
+        };
+        ret.cache = Some(RwLock::new(db));
+        Ok(ret)
+    }
+
+    /// Lock the database; the lock is released when the returned file is dropped
+    fn lock(&self, exclusive: bool) -> Result<fs::File> {
+        let lock_file = format!("{}.lock", self.filename);
+        let f = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_file)
+            .with_context(|| format!("Could not open {}", lock_file))?;
+        if exclusive {
+            f.lock_exclusive()?;
+        } else {
+            f.lock_shared()?;
+        }
+        Ok(f)
+    }
+
+    /// Migrate an older database file to the current version
+    ///
+    /// The original file is kept next to the database as `<filename>.v<version>.bak`.
+    fn upgrade(&self) -> Result<()> {
+        let _lock = self.lock(true)?;
+        let Some(mut raw) = self.load_raw()? else {
+            return Ok(());
+        };
+        let version = migrations::version(&raw);
+        if version >= migrations::CURRENT_VERSION {
+            return Ok(());
+        }
+
+        let backup_file = format!("{}.v{}.bak", self.filename, version);
+        fs::copy(&self.filename, &backup_file)
+            .with_context(|| format!("Could not back up database to {}", backup_file))?;
+
+        migrations::migrate(&mut raw)?;
+        let db = serde_json::from_value(raw)
+            .with_context(|| format!("Could not parse migrated {}", self.filename))?;
+        self.save(&db)
+    }
+
+    /// Load the JSON of the database, must be called while holding the lock
+    ///
+    /// A missing file is an empty database, but a file we can't read is an
+    /// error: treating it as empty would wipe it on the next save.
+    fn load_raw(&self) -> Result<Option<serde_json::Value>> {
+        let f = match fs::File::open(&self.filename) {
+            Ok(f) => f,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Could not open {}", self.filename));
+            }
+        };
+        let raw = serde_json::from_reader(BufReader::new(f))
+            .with_context(|| format!("Could not parse {}", self.filename))?;
+        Ok(Some(raw))
+    }
+
+    /// Load the database, must be called while holding the lock
+    fn load(&self) -> Result<FullDatabase> {
+        let Some(mut raw) = self.load_raw()? else {
+            return Ok(FullDatabase::default());
+        };
+        // Normally a no-op, unless another process wrote an older version since we opened it
+        migrations::migrate(&mut raw)?;
+        serde_json::from_value(raw).with_context(|| format!("Could not parse {}", self.filename))
+    }
+
+    /// Save the database, must be called while holding the exclusive lock
+    fn save(&self, db: &FullDatabase) -> Result<()> {
+        let tmp_file = format!("{}.tmp", self.filename);
+        let mut w = BufWriter::new(fs::File::create(&tmp_file)?);
+        serde_json::to_writer_pretty(&mut w, &db)?;
+        let f = w.into_inner().map_err(|e| e.into_error())?;
+        f.sync_all()?;
+        fs::rename(&tmp_file, &self.filename)
+            .with_context(|| format!("Could not replace {}", self.filename))?;
+        // The rename itself only survives a crash once the directory is synced
+        #[cfg(unix)]
+        {
+            use std::path::Path;
+            let dir = Path::new(&self.filename)
+                .parent()
+                .filter(|d| !d.as_os_str().is_empty())
+                .unwrap_or(Path::new("."));
+            fs::File::open(dir)?.sync_all()?;
+        }
+        Ok(())
+    }
+
+    fn read<R>(&self, block: impl FnOnce(&FullDatabase) -> R) -> Result<R> {
+        if let Some(cache) = &self.cache {
+            return Ok(block(&cache.read().unwrap()));
+        }
+
+        let _lock = self.lock(false)?;
+        Ok(block(&self.load()?))
+    }
+
+    fn modify<R>(&self, block: impl FnOnce(&mut FullDatabase) -> Result<R>) -> Result<R> {
+        if let Some(cache) = &self.cache {
+            // Holding the write lock serializes all changes. Apply the change to a
+            // copy, so that the cache stays in sync with the disk if saving fails.
+            let mut cached = cache.write().unwrap();
+            let mut db = cached.clone();
+            let ret = block(&mut db)?;
+            {
+                let _lock = self.lock(true)?;
+                self.save(&db)?;
+            }
+            *cached = db;
+            return Ok(ret);
+        }
+
+        let _lock = self.lock(true)?;
+        let mut db = self.load()?;
+        let ret = block(&mut db)?;
+        self.save(&db)?;
+        Ok(ret)
+    }
+}
+
+impl Storage for JsonStorage {
+    fn tasks(&self) -> Result<HSTable<Task>> {
+        self.read(|db| db.tasks.clone())
+    }
+
+    fn project_tasks(&self, project_uid: &str) -> Result<Vec<Task>> {
+        self.read(|db| db.tasks.get_many(project_uid).cloned().collect())
+    }
+
+    fn task(&self, project_uid: &str, uid: &str) -> Result<Option<Task>> {
+        self.read(|db| db.tasks.get2(project_uid, uid).cloned())
+    }
+
+    fn projects(&self) -> Result<HSTable<Project>> {
+        self.read(|db| db.projects.clone())
+    }
+
+    fn project(&self, uid: &str) -> Result<Option<Project>> {
+        self.read(|db| db.projects.get1(uid).cloned())
+    }
+
+    fn new_project(&self) -> Result<String> {
+        let uid = unique_id();
+        self.modify(|db| {
+            db.projects.insert(Project {
+                uid: uid.clone(),
+                name: "New project".to_owned(),
+                ..Default::default()
+            });
+            Ok(())
+        })?;
+        Ok(uid)
+    }
+
+    fn delete_project(&self, uid: &str) -> Result<()> {
+        self.modify(|db| {
+            db.projects.remove1(uid);
+            db.tasks.remove_many(uid);
+            db.history.remove_many(uid);
+            db.snapshots.remove_many(uid);
+            Ok(())
+        })
+    }
+
+    fn upsert_tasks(&self, updates: Vec<TaskUpdate>) -> Result<()> {
+        self.modify(|db| {
+            let mut changes = vec![];
+            for update in updates {
+                let task_count = db.tasks.get_many(&update.project_uid).count();
+                let before = db.tasks.get2(&update.project_uid, &update.uid).cloned();
+                let after = update.upsert(before.as_ref(), task_count);
+                let change = Change::Task {
+                    before,
+                    after: Some(after),
+                };
+                // Later updates see the tasks that earlier ones created
+                db.apply(&change)?;
+                changes.push(change);
+            }
+            history::record(db, Change::batch(changes))
+        })
+    }
+
+    fn delete_task(&self, project_uid: &str, uid: &str) -> Result<()> {
+        self.modify(|db| {
+            let before = db.tasks.get2(project_uid, uid).cloned();
+            history::record(
+                db,
+                Change::Task {
+                    before,
+                    after: None,
+                },
+            )
+        })
+    }
+
+    fn with_project(&self, project_uid: &str, block: &mut dyn FnMut(&mut Project)) -> Result<()> {
+        self.modify(|db| {
+            let Some(before) = db.projects.get1(project_uid).cloned() else {
+                return Ok(());
+            };
+            let mut after = before.clone();
+            block(&mut after);
+            history::record(db, Change::Project { before, after })
+        })
+    }
+
+    fn with_task(
+        &self,
+        project_uid: &str,
+        uid: &str,
+        block: &mut dyn FnMut(&mut Task),
+    ) -> Result<()> {
+        self.modify(|db| {
+            let Some(before) = db.tasks.get2(project_uid, uid).cloned() else {
+                return Ok(());
+            };
+            let mut after = before.clone();
+            block(&mut after);
+            history::record(
+                db,
+                Change::Task {
+                    before: Some(before),
+                    after: Some(after),
+                },
+            )
+        })
+    }
+
+    fn change_log(&self, project_uid: &str) -> Result<Vec<LogEntry>> {
+        self.read(|db| db.log_entries(project_uid))?
+    }
+
+    fn undo(&self, project_uid: &str) -> Result<Option<Change>> {
+        self.modify(|db| history::undo(db, project_uid))
+    }
+
+    fn redo(&self, project_uid: &str) -> Result<Option<Change>> {
+        self.modify(|db| history::redo(db, project_uid))
+    }
+
+    fn snapshots(&self, project_uid: &str) -> Result<Vec<Snapshot>> {
+        self.read(|db| {
+            db.snapshots
+                .get_many(project_uid)
+                .cloned()
+                .sorted_by_key(|s| s.created)
+                .collect()
+        })
+    }
+
+    fn snapshot(&self, project_uid: &str, uid: &str) -> Result<Option<Snapshot>> {
+        self.read(|db| db.snapshots.get2(project_uid, uid).cloned())
+    }
+
+    fn create_snapshot(&self, project_uid: &str, name: &str) -> Result<Option<String>> {
+        self.modify(|db| {
+            let Some(project) = db.projects.get1(project_uid).cloned() else {
+                return Ok(None);
+            };
+            let uid = unique_id();
+            db.snapshots.insert(Snapshot {
+                project_uid: project_uid.to_owned(),
+                uid: uid.clone(),
+                name: name.to_owned(),
+                created: Utc::now(),
+                project,
+                tasks: db.tasks.get_many(project_uid).cloned().collect(),
+            });
+            Ok(Some(uid))
+        })
+    }
+
+    fn pool(&self) -> Result<Vec<PoolMember>> {
+        self.read(|db| db.pool.clone())
+    }
+
+    fn with_pool(&self, block: &mut dyn FnMut(&mut Vec<PoolMember>)) -> Result<()> {
+        self.modify(|db| {
+            block(&mut db.pool);
+            Ok(())
+        })
+    }
+}
+
+impl ChangeLog for FullDatabase {
+    fn apply(&mut self, change: &Change) -> Result<()> {
+        match change {
+            Change::Task {
+                before,
+                after: None,
+            } => {
+                if let Some(before) = before {
+                    self.tasks.remove2(&before.project_uid, &before.uid);
+                }
+            }
+            Change::Task {
+                after: Some(after), ..
+            } => self.tasks.insert(after.clone()),
+            Change::Project { after, .. } => self.projects.insert(after.clone()),
+            Change::Batch { changes } => {
+                for change in changes {
+                    self.apply(change)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn log_entries(&self, project_uid: &str) -> Result<Vec<LogEntry>> {
+        Ok(self.history.get_many(project_uid).cloned().collect())
+    }
+
+    fn append(&mut self, project_uid: &str, event: Event) -> Result<u64> {
+        let seq = self
+            .history
+            .get_many(project_uid)
+            .last()
+            .map(|e| e.seq + 1)
+            .unwrap_or_default();
+        self.history.insert(LogEntry {
+            project_uid: project_uid.to_owned(),
+            seq,
+            event,
+        });
+        Ok(seq)
+    }
+
+    fn replace_log(&mut self, project_uid: &str, entries: Vec<LogEntry>) -> Result<()> {
+        self.history.remove_many(project_uid);
+        for entry in entries {
+            self.history.insert(entry);
+        }
+        Ok(())
+    }
+}
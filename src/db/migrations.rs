@@ -0,0 +1,269 @@
+//! Upgrades for older versions of the JSON database
+//!
+//! Adding a field with a sensible default doesn't need a migration, serde
+//! takes care of that. Renaming a field or changing its meaning does: bump
+//! `CURRENT_VERSION` and add a function to `MIGRATIONS` that rewrites the
+//! JSON of the previous version.
+//!
+//! Projects and tasks are also stored in snapshots and in the undo history,
+//! so a migration that rewrites them has to rewrite those copies as well.
+use anyhow::{bail, Result};
+use serde_json::{json, Value};
+
+/// The version of the database format written by this version of planny
+pub const CURRENT_VERSION: u32 = 3;
+
+type Migration = fn(&mut Value) -> Result<()>;
+
+/// `MIGRATIONS[n]` upgrades a database from version `n` to version `n + 1`
+const MIGRATIONS: [Migration; CURRENT_VERSION as usize] =
+    [v0_add_version, v1_people_list, v2_dependency_map];
+
+/// The version of a database, files from before we versioned them are version 0
+pub fn version(db: &Value) -> u32 {
+    db.get("version")
+        .and_then(|v| v.as_u64())
+        .map(|v| v as u32)
+        .unwrap_or(0)
+}
+
+/// Upgrade a database to the current version, one version at a time
+pub fn migrate(db: &mut Value) -> Result<()> {
+    let from = version(db);
+    if from > CURRENT_VERSION {
+        bail!(
+            "Database is version {}, but this version of planny only understands up to version {}",
+            from,
+            CURRENT_VERSION
+        );
+    }
+
+    for (v, migration) in MIGRATIONS.iter().enumerate().skip(from as usize) {
+        migration(db)?;
+        set_version(db, v as u32 + 1)?;
+    }
+    Ok(())
+}
+
+fn set_version(db: &mut Value, version: u32) -> Result<()> {
+    let Some(obj) = db.as_object_mut() else {
+        bail!("Database is not a JSON object");
+    };
+    obj.insert("version".to_owned(), version.into());
+    Ok(())
+}
+
+/// Version 0 is identical to version 1, except it doesn't say so
+fn v0_add_version(_db: &mut Value) -> Result<()> {
+    Ok(())
+}
+
+/// Projects used to only store how many people work on them
+fn v1_people_list(db: &mut Value) -> Result<()> {
+    for_each_project(db, project_people_list)
+}
+
+/// Turn the number of people of a project into that many people
+pub fn project_people_list(project: &mut Value) {
+    if let Some(people) = project.get_mut("people") {
+        people_list(people);
+    }
+}
+
+/// Turn a number of people into that many people, named like a new team
+pub fn people_list(people: &mut Value) {
+    let Some(n) = people.as_u64() else {
+        return;
+    };
+    *people = (1..=n.max(1))
+        .map(|i| {
+            json!({
+                "uid": format!("person-{}", i),
+                "name": format!("Person {}", i),
+                "capacity": 1.0,
+                "skills": [],
+                "start": null,
+                "vacations": [],
+            })
+        })
+        .collect();
+}
+
+/// Dependencies used to be a list of uids, which all meant finish-to-start
+fn v2_dependency_map(db: &mut Value) -> Result<()> {
+    for_each_task(db, task_dependency_map)
+}
+
+/// Turn the list of dependencies of a task into dependencies by uid
+pub fn task_dependency_map(task: &mut Value) {
+    if let Some(dependencies) = task.get_mut("dependencies") {
+        dependency_map(dependencies);
+    }
+}
+
+/// Turn a list of uids into finish-to-start dependencies by uid
+pub fn dependency_map(dependencies: &mut Value) {
+    let Some(uids) = dependencies.as_array() else {
+        return;
+    };
+    *dependencies = uids
+        .iter()
+        .filter_map(Value::as_str)
+        .map(|uid| (uid.to_owned(), json!({ "kind": "fs", "lag": 0.0 })))
+        .collect::<serde_json::Map<_, _>>()
+        .into();
+}
+
+/// Call `f` on every project, including the copies in snapshots and the undo history
+fn for_each_project(db: &mut Value, mut f: impl FnMut(&mut Value)) -> Result<()> {
+    let Some(obj) = db.as_object_mut() else {
+        bail!("Database is not a JSON object");
+    };
+    for (key, value) in obj.iter_mut() {
+        let items = value.as_array_mut().into_iter().flatten();
+        match key.as_str() {
+            "projects" => items.for_each(&mut f),
+            "snapshots" => items
+                .filter_map(|snapshot| snapshot.get_mut("project"))
+                .for_each(&mut f),
+            "history" => items
+                .filter_map(|entry| entry.get_mut("event"))
+                .for_each(|event| for_each_change(event, "project", &mut f)),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Call `f` on every task, including the copies in snapshots and the undo history
+fn for_each_task(db: &mut Value, mut f: impl FnMut(&mut Value)) -> Result<()> {
+    let Some(obj) = db.as_object_mut() else {
+        bail!("Database is not a JSON object");
+    };
+    for (key, value) in obj.iter_mut() {
+        let items = value.as_array_mut().into_iter().flatten();
+        match key.as_str() {
+            "tasks" => items.for_each(&mut f),
+            "snapshots" => items
+                .filter_map(|snapshot| snapshot.get_mut("tasks"))
+                .filter_map(Value::as_array_mut)
+                .flatten()
+                .for_each(&mut f),
+            "history" => items
+                .filter_map(|entry| entry.get_mut("event"))
+                .for_each(|event| for_each_change(event, "task", &mut f)),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Call `f` on the states before and after a change of `kind` in an event of the undo history
+pub fn for_each_change(event: &mut Value, kind: &str, mut f: impl FnMut(&mut Value)) {
+    let Some(change) = event.get_mut("change") else {
+        return;
+    };
+    if change.get("kind").and_then(Value::as_str) != Some(kind) {
+        return;
+    }
+    for state in ["before", "after"] {
+        if let Some(value) = change.get_mut(state).filter(|v| v.is_object()) {
+            f(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        datamodel::{Dependency, Project, Snapshot, Task},
+        history::{Change, Event, LogEntry},
+    };
+
+    #[test]
+    fn version_0_is_migrated_everywhere() {
+        let project = json!({ "uid": "p", "name": "Old", "people": 2 });
+        let task = json!({ "project_uid": "p", "uid": "b", "dependencies": ["a"] });
+        let mut db = json!({
+            "projects": [project],
+            "tasks": [task],
+            "snapshots": [{
+                "project_uid": "p",
+                "uid": "s",
+                "name": "Before",
+                "created": "2026-01-05T00:00:00Z",
+                "project": project,
+                "tasks": [task],
+            }],
+            "history": [
+                {
+                    "project_uid": "p",
+                    "seq": 1,
+                    "event": {
+                        "type": "change",
+                        "change": { "kind": "project", "before": project, "after": project },
+                    },
+                },
+                {
+                    "project_uid": "p",
+                    "seq": 2,
+                    "event": {
+                        "type": "change",
+                        "change": { "kind": "task", "before": null, "after": task },
+                    },
+                },
+            ],
+        });
+        migrate(&mut db).unwrap();
+        assert_eq!(version(&db), CURRENT_VERSION);
+
+        let check_project = |p: &Project| {
+            assert_eq!(p.people.len(), 2);
+            assert_eq!(p.people[1].name, "Person 2");
+        };
+        let check_task = |t: &Task| {
+            assert_eq!(
+                t.dependencies.iter().collect::<Vec<_>>(),
+                [(&"a".to_owned(), &Dependency::default())]
+            );
+        };
+        let projects: Vec<Project> = serde_json::from_value(db["projects"].clone()).unwrap();
+        let tasks: Vec<Task> = serde_json::from_value(db["tasks"].clone()).unwrap();
+        let snapshots: Vec<Snapshot> = serde_json::from_value(db["snapshots"].clone()).unwrap();
+        let history: Vec<LogEntry> = serde_json::from_value(db["history"].clone()).unwrap();
+        check_project(&projects[0]);
+        check_task(&tasks[0]);
+        check_project(&snapshots[0].project);
+        check_task(&snapshots[0].tasks[0]);
+        for entry in &history {
+            match &entry.event {
+                Event::Change {
+                    change: Change::Project { before, after },
+                } => {
+                    check_project(before);
+                    check_project(after);
+                }
+                Event::Change {
+                    change: Change::Task { after, .. },
+                } => check_task(after.as_ref().unwrap()),
+                _ => panic!("unexpected event {:?}", entry.event),
+            }
+        }
+    }
+
+    #[test]
+    fn current_version_is_left_alone() {
+        let mut db =
+            json!({ "version": CURRENT_VERSION, "tasks": [{ "dependencies": { "a": {} } }] });
+        let before = db.clone();
+        migrate(&mut db).unwrap();
+        assert_eq!(db, before);
+    }
+
+    #[test]
+    fn newer_versions_are_refused() {
+        let mut db = json!({ "version": CURRENT_VERSION + 1 });
+        assert!(migrate(&mut db).is_err());
+    }
+}
@@ -0,0 +1,174 @@
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::{
+    datamodel::{PoolMember, Project, Snapshot, Task, TaskUpdate},
+    history::{Change, LogEntry, UndoStacks},
+    hstable::HSTable,
+};
+
+pub use self::{json::JsonStorage, sqlite::SqliteStorage};
+
+mod json;
+mod migrations;
+mod sqlite;
+
+/// The operations a storage backend needs to support
+///
+/// Every operation is atomic on its own; callers don't get to hold on to
+/// a transaction between calls. Every change to a task or project is recorded
+/// in the change log of its project, so that it can be undone.
+pub trait Storage: Send + Sync {
+    fn tasks(&self) -> Result<HSTable<Task>>;
+    fn project_tasks(&self, project_uid: &str) -> Result<Vec<Task>>;
+    fn task(&self, project_uid: &str, uid: &str) -> Result<Option<Task>>;
+    fn projects(&self) -> Result<HSTable<Project>>;
+    fn project(&self, uid: &str) -> Result<Option<Project>>;
+    fn new_project(&self) -> Result<String>;
+    /// Delete a project along with its tasks, history and snapshots
+    fn delete_project(&self, uid: &str) -> Result<()>;
+    /// Create or update tasks, recorded as a single change
+    fn upsert_tasks(&self, updates: Vec<TaskUpdate>) -> Result<()>;
+    fn delete_task(&self, project_uid: &str, uid: &str) -> Result<()>;
+    fn with_project(&self, project_uid: &str, block: &mut dyn FnMut(&mut Project)) -> Result<()>;
+    fn with_task(
+        &self,
+        project_uid: &str,
+        uid: &str,
+        block: &mut dyn FnMut(&mut Task),
+    ) -> Result<()>;
+
+    /// All changes made to a project, oldest first
+    fn change_log(&self, project_uid: &str) -> Result<Vec<LogEntry>>;
+    /// Undo the last change to a project, returns the change if there was one
+    fn undo(&self, project_uid: &str) -> Result<Option<Change>>;
+    /// Redo the last undone change to a project, returns the change if there was one
+    fn redo(&self, project_uid: &str) -> Result<Option<Change>>;
+
+    /// All snapshots of a project, oldest first
+    fn snapshots(&self, project_uid: &str) -> Result<Vec<Snapshot>>;
+    fn snapshot(&self, project_uid: &str, uid: &str) -> Result<Option<Snapshot>>;
+    /// Save a copy of the current state of a project, returns the uid of the snapshot
+    fn create_snapshot(&self, project_uid: &str, name: &str) -> Result<Option<String>>;
+
+    /// The people of the organisation, that projects share
+    fn pool(&self) -> Result<Vec<PoolMember>>;
+    /// Changes to the pool belong to no project, so they can't be undone
+    fn with_pool(&self, block: &mut dyn FnMut(&mut Vec<PoolMember>)) -> Result<()>;
+}
+
+pub struct Database {
+    storage: Box<dyn Storage>,
+}
+
+impl Database {
+    /// Open the database at the given file
+    ///
+    /// Files ending in `.sqlite` or `.db` are opened as SQLite databases,
+    /// everything else is treated as a JSON file.
+    ///
+    /// If `cached` is set, a JSON database is kept in memory between requests.
+    /// SQLite does its own caching, so the flag doesn't apply there.
+    pub fn new(filename: String, cached: bool) -> Result<Self> {
+        let storage: Box<dyn Storage> =
+            match Path::new(&filename).extension().and_then(|e| e.to_str()) {
+                Some("sqlite" | "db") => Box::new(SqliteStorage::open(&filename)?),
+                _ if cached => Box::new(JsonStorage::cached(filename)?),
+                _ => Box::new(JsonStorage::open(filename)?),
+            };
+        Ok(Self { storage })
+    }
+
+    pub fn tasks(&self) -> Result<HSTable<Task>> {
+        self.storage.tasks()
+    }
+
+    /// The tasks of one project, cheaper than all tasks
+    pub fn project_tasks(&self, project_uid: &str) -> Result<Vec<Task>> {
+        self.storage.project_tasks(project_uid)
+    }
+
+    pub fn task(&self, project_uid: &str, uid: &str) -> Result<Option<Task>> {
+        self.storage.task(project_uid, uid)
+    }
+
+    pub fn projects(&self) -> Result<HSTable<Project>> {
+        self.storage.projects()
+    }
+
+    pub fn project(&self, uid: &str) -> Result<Option<Project>> {
+        self.storage.project(uid)
+    }
+
+    pub fn new_project(&self) -> Result<String> {
+        self.storage.new_project()
+    }
+
+    pub fn delete_project(&self, uid: &str) -> Result<()> {
+        self.storage.delete_project(uid)
+    }
+
+    pub fn upsert_task(&self, update: TaskUpdate) -> Result<()> {
+        self.upsert_tasks(vec![update])
+    }
+
+    /// Create or update several tasks at once, so they are undone together
+    pub fn upsert_tasks(&self, updates: Vec<TaskUpdate>) -> Result<()> {
+        assert!(updates.iter().all(|u| !u.uid.is_empty()));
+        self.storage.upsert_tasks(updates)
+    }
+
+    pub fn delete_task(&self, project_uid: &str, uid: &str) -> Result<()> {
+        self.storage.delete_task(project_uid, uid)
+    }
+
+    pub fn with_project(
+        &self,
+        project_uid: &str,
+        mut block: impl FnMut(&mut Project),
+    ) -> Result<()> {
+        self.storage.with_project(project_uid, &mut block)
+    }
+
+    pub fn with_task(
+        &self,
+        project_uid: &str,
+        uid: &str,
+        mut block: impl FnMut(&mut Task),
+    ) -> Result<()> {
+        self.storage.with_task(project_uid, uid, &mut block)
+    }
+
+    pub fn undo(&self, project_uid: &str) -> Result<Option<Change>> {
+        self.storage.undo(project_uid)
+    }
+
+    pub fn redo(&self, project_uid: &str) -> Result<Option<Change>> {
+        self.storage.redo(project_uid)
+    }
+
+    pub fn undo_stacks(&self, project_uid: &str) -> Result<UndoStacks> {
+        Ok(UndoStacks::replay(&self.storage.change_log(project_uid)?))
+    }
+
+    pub fn snapshots(&self, project_uid: &str) -> Result<Vec<Snapshot>> {
+        self.storage.snapshots(project_uid)
+    }
+
+    pub fn snapshot(&self, project_uid: &str, uid: &str) -> Result<Option<Snapshot>> {
+        self.storage.snapshot(project_uid, uid)
+    }
+
+    pub fn create_snapshot(&self, project_uid: &str, name: &str) -> Result<Option<String>> {
+        self.storage.create_snapshot(project_uid, name)
+    }
+
+    pub fn pool(&self) -> Result<Vec<PoolMember>> {
+        self.storage.pool()
+    }
+
+    pub fn with_pool(&self, mut block: impl FnMut(&mut Vec<PoolMember>)) -> Result<()> {
+        self.storage.with_pool(&mut block)
+    }
+}
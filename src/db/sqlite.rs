@@ -0,0 +1,891 @@
+use std::{collections::HashMap, fs, sync::Mutex};
+
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
+use rusqlite::{
+    params,
+    types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef},
+    Connection, OptionalExtension, Row, ToSql, Transaction,
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    datamodel::{Dependency, Person, PoolMember, Project, Snapshot, Task, TaskUpdate},
+    history::{self, Change, ChangeLog, Event, LogEntry},
+    hstable::HSTable,
+    ids::unique_id,
+};
+
+use super::{migrations, Storage};
+
+/// Stores the database in an embedded SQLite file
+///
+/// Projects, tasks and dependencies each get their own table, so
+/// changing a single task only touches the rows of that task.
+pub struct SqliteStorage {
+    conn: Mutex<Connection>,
+}
+
+enum Migration {
+    Sql(&'static str),
+    /// For what SQL can't do, like rewriting the JSON stored in a column
+    Rust(fn(&Transaction) -> Result<()>),
+}
+use Migration::{Rust, Sql};
+
+/// `MIGRATIONS[n]` upgrades the schema from `user_version` n to n + 1
+const MIGRATIONS: &[Migration] = &[
+    Sql("
+CREATE TABLE IF NOT EXISTS projects (
+    uid TEXT PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL,
+    people INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS tasks (
+    project_uid TEXT NOT NULL,
+    uid TEXT NOT NULL,
+    type TEXT NOT NULL,
+    id TEXT NOT NULL,
+    title TEXT NOT NULL,
+    estimate REAL,
+    risk TEXT,
+    PRIMARY KEY (project_uid, uid)
+);
+
+CREATE TABLE IF NOT EXISTS dependencies (
+    project_uid TEXT NOT NULL,
+    task_uid TEXT NOT NULL,
+    dependency_uid TEXT NOT NULL,
+    PRIMARY KEY (project_uid, task_uid, dependency_uid),
+    FOREIGN KEY (project_uid, task_uid) REFERENCES tasks (project_uid, uid) ON DELETE CASCADE
+);
+"),
+    Sql("
+CREATE TABLE history (
+    project_uid TEXT NOT NULL,
+    seq INTEGER NOT NULL,
+    event TEXT NOT NULL,
+    PRIMARY KEY (project_uid, seq)
+);
+"),
+    Sql("
+CREATE TABLE snapshots (
+    project_uid TEXT NOT NULL,
+    uid TEXT NOT NULL,
+    name TEXT NOT NULL,
+    created TEXT NOT NULL,
+    project TEXT NOT NULL,
+    tasks TEXT NOT NULL,
+    PRIMARY KEY (project_uid, uid)
+);
+"),
+    Sql("
+ALTER TABLE projects ADD COLUMN seed INTEGER NOT NULL DEFAULT 0;
+"),
+    Sql("
+ALTER TABLE projects ADD COLUMN iterations INTEGER NOT NULL DEFAULT 1000;
+ALTER TABLE projects ADD COLUMN tolerance REAL;
+"),
+    Sql("
+ALTER TABLE projects ADD COLUMN calendar TEXT NOT NULL DEFAULT '{}';
+"),
+    Sql("
+ALTER TABLE projects RENAME COLUMN people TO head_count;
+ALTER TABLE projects ADD COLUMN people TEXT NOT NULL DEFAULT '[]';
+-- A bare count reads back as that many full-time people
+UPDATE projects SET people = CAST(head_count AS TEXT);
+ALTER TABLE projects DROP COLUMN head_count;
+"),
+    Sql("
+ALTER TABLE tasks ADD COLUMN skills TEXT NOT NULL DEFAULT '[]';
+ALTER TABLE tasks ADD COLUMN assignee TEXT;
+"),
+    Sql("
+ALTER TABLE tasks ADD COLUMN status TEXT NOT NULL DEFAULT 'todo';
+ALTER TABLE tasks ADD COLUMN started TEXT;
+ALTER TABLE tasks ADD COLUMN finished TEXT;
+ALTER TABLE tasks ADD COLUMN remaining REAL;
+"),
+    Sql("
+ALTER TABLE tasks ADD COLUMN distribution TEXT NOT NULL DEFAULT 'lognormal';
+ALTER TABLE tasks ADD COLUMN optimistic REAL;
+ALTER TABLE tasks ADD COLUMN pessimistic REAL;
+ALTER TABLE tasks ADD COLUMN sigma REAL;
+"),
+    Sql("
+ALTER TABLE projects ADD COLUMN confidence REAL NOT NULL DEFAULT 0.8;
+-- A date as a JSON string, or a number of days
+ALTER TABLE tasks ADD COLUMN deadline TEXT;
+"),
+    Sql("
+ALTER TABLE dependencies ADD COLUMN kind TEXT NOT NULL DEFAULT 'fs';
+ALTER TABLE dependencies ADD COLUMN lag REAL NOT NULL DEFAULT 0;
+"),
+    Sql("
+ALTER TABLE tasks ADD COLUMN parent TEXT;
+"),
+    Sql("
+ALTER TABLE projects ADD COLUMN scheduling TEXT NOT NULL DEFAULT 'random';
+ALTER TABLE tasks ADD COLUMN priority INTEGER;
+"),
+    Sql("
+ALTER TABLE projects ADD COLUMN efficiency_loss REAL NOT NULL DEFAULT 0.1;
+ALTER TABLE tasks ADD COLUMN max_people INTEGER;
+"),
+    Sql("
+-- Only for a task in another project
+ALTER TABLE dependencies ADD COLUMN dependency_project_uid TEXT;
+"),
+    Sql("
+CREATE TABLE pool (
+    uid TEXT PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL,
+    capacity REAL NOT NULL DEFAULT 1.0
+);
+"),
+    Rust(people_list),
+    Rust(dependency_map),
+];
+
+impl SqliteStorage {
+    pub fn open(filename: &str) -> Result<Self> {
+        let mut conn = Connection::open(filename)?;
+        conn.pragma_update(None, "foreign_keys", true)?;
+        migrate(&mut conn, filename)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn read<R>(&self, block: impl FnOnce(&Connection) -> Result<R>) -> Result<R> {
+        let conn = self.conn.lock().unwrap();
+        block(&conn)
+    }
+
+    fn modify<R>(&self, block: impl FnOnce(&mut Tables) -> Result<R>) -> Result<R> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let ret = block(&mut Tables(&tx))?;
+        tx.commit()?;
+        Ok(ret)
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn tasks(&self) -> Result<HSTable<Task>> {
+        self.read(|conn| Ok(HSTable::from_iter(read_tasks(conn, None)?)))
+    }
+
+    fn project_tasks(&self, project_uid: &str) -> Result<Vec<Task>> {
+        self.read(|conn| read_tasks(conn, Some(project_uid)))
+    }
+
+    fn task(&self, project_uid: &str, uid: &str) -> Result<Option<Task>> {
+        self.read(|conn| read_task(conn, project_uid, uid))
+    }
+
+    fn projects(&self) -> Result<HSTable<Project>> {
+        self.read(|conn| {
+            let mut stmt = conn.prepare("SELECT * FROM projects")?;
+            let projects = stmt
+                .query_map([], project_from_row)?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            Ok(HSTable::from_iter(projects))
+        })
+    }
+
+    fn project(&self, uid: &str) -> Result<Option<Project>> {
+        self.read(|conn| read_project(conn, uid))
+    }
+
+    fn new_project(&self) -> Result<String> {
+        let uid = unique_id();
+        self.modify(|tables| {
+            write_project(
+                tables.0,
+                &Project {
+                    uid: uid.clone(),
+                    name: "New project".to_owned(),
+                    ..Default::default()
+                },
+            )
+        })?;
+        Ok(uid)
+    }
+
+    fn delete_project(&self, uid: &str) -> Result<()> {
+        self.modify(|tables| {
+            for table in ["projects", "tasks", "history", "snapshots"] {
+                let key = if table == "projects" {
+                    "uid"
+                } else {
+                    "project_uid"
+                };
+                tables
+                    .0
+                    .execute(&format!("DELETE FROM {table} WHERE {key} = ?1"), [uid])?;
+            }
+            Ok(())
+        })
+    }
+
+    fn upsert_tasks(&self, updates: Vec<TaskUpdate>) -> Result<()> {
+        self.modify(|tables| {
+            let mut changes = vec![];
+            for update in updates {
+                let task_count: usize = tables.0.query_row(
+                    "SELECT COUNT(*) FROM tasks WHERE project_uid = ?1",
+                    [&update.project_uid],
+                    |row| row.get(0),
+                )?;
+                let before = read_task(tables.0, &update.project_uid, &update.uid)?;
+                let after = update.upsert(before.as_ref(), task_count);
+                let change = Change::Task {
+                    before,
+                    after: Some(after),
+                };
+                // Later updates see the tasks that earlier ones created
+                tables.apply(&change)?;
+                changes.push(change);
+            }
+            history::record(tables, Change::batch(changes))
+        })
+    }
+
+    fn delete_task(&self, project_uid: &str, uid: &str) -> Result<()> {
+        self.modify(|tables| {
+            let before = read_task(tables.0, project_uid, uid)?;
+            history::record(
+                tables,
+                Change::Task {
+                    before,
+                    after: None,
+                },
+            )
+        })
+    }
+
+    fn with_project(&self, project_uid: &str, block: &mut dyn FnMut(&mut Project)) -> Result<()> {
+        self.modify(|tables| {
+            let Some(before) = read_project(tables.0, project_uid)? else {
+                return Ok(());
+            };
+            let mut after = before.clone();
+            block(&mut after);
+            history::record(tables, Change::Project { before, after })
+        })
+    }
+
+    fn with_task(
+        &self,
+        project_uid: &str,
+        uid: &str,
+        block: &mut dyn FnMut(&mut Task),
+    ) -> Result<()> {
+        self.modify(|tables| {
+            let Some(before) = read_task(tables.0, project_uid, uid)? else {
+                return Ok(());
+            };
+            let mut after = before.clone();
+            block(&mut after);
+            history::record(
+                tables,
+                Change::Task {
+                    before: Some(before),
+                    after: Some(after),
+                },
+            )
+        })
+    }
+
+    fn change_log(&self, project_uid: &str) -> Result<Vec<LogEntry>> {
+        self.read(|conn| Tables(conn).log_entries(project_uid))
+    }
+
+    fn undo(&self, project_uid: &str) -> Result<Option<Change>> {
+        self.modify(|tables| history::undo(tables, project_uid))
+    }
+
+    fn redo(&self, project_uid: &str) -> Result<Option<Change>> {
+        self.modify(|tables| history::redo(tables, project_uid))
+    }
+
+    fn snapshots(&self, project_uid: &str) -> Result<Vec<Snapshot>> {
+        self.read(|conn| {
+            let mut stmt =
+                conn.prepare("SELECT * FROM snapshots WHERE project_uid = ?1 ORDER BY created")?;
+            let snapshots = stmt
+                .query_map([project_uid], snapshot_from_row)?
+                .collect::<rusqlite::Result<_>>()?;
+            Ok(snapshots)
+        })
+    }
+
+    fn snapshot(&self, project_uid: &str, uid: &str) -> Result<Option<Snapshot>> {
+        self.read(|conn| {
+            Ok(conn
+                .query_row(
+                    "SELECT * FROM snapshots WHERE project_uid = ?1 AND uid = ?2",
+                    [project_uid, uid],
+                    snapshot_from_row,
+                )
+                .optional()?)
+        })
+    }
+
+    fn create_snapshot(&self, project_uid: &str, name: &str) -> Result<Option<String>> {
+        self.modify(|tables| {
+            let Some(project) = read_project(tables.0, project_uid)? else {
+                return Ok(None);
+            };
+            let snapshot = Snapshot {
+                project_uid: project_uid.to_owned(),
+                uid: unique_id(),
+                name: name.to_owned(),
+                created: Utc::now(),
+                project,
+                tasks: read_tasks(tables.0, Some(project_uid))?,
+            };
+            tables.0.execute(
+                "INSERT INTO snapshots (project_uid, uid, name, created, project, tasks)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    snapshot.project_uid,
+                    snapshot.uid,
+                    snapshot.name,
+                    snapshot.created,
+                    AsJson(&snapshot.project),
+                    AsJson(&snapshot.tasks),
+                ],
+            )?;
+            Ok(Some(snapshot.uid))
+        })
+    }
+
+    fn pool(&self) -> Result<Vec<PoolMember>> {
+        self.read(read_pool)
+    }
+
+    fn with_pool(&self, block: &mut dyn FnMut(&mut Vec<PoolMember>)) -> Result<()> {
+        self.modify(|tables| {
+            let mut pool = read_pool(tables.0)?;
+            block(&mut pool);
+            // Rewritten in full, so the pool keeps its order
+            tables.0.execute("DELETE FROM pool", [])?;
+            for member in &pool {
+                tables.0.execute(
+                    "INSERT INTO pool (uid, name, capacity) VALUES (?1, ?2, ?3)",
+                    params![member.uid, member.name, member.capacity],
+                )?;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// The tables of the database, inside a transaction
+struct Tables<'a>(&'a Connection);
+
+impl ChangeLog for Tables<'_> {
+    fn apply(&mut self, change: &Change) -> Result<()> {
+        match change {
+            Change::Task {
+                before,
+                after: None,
+            } => {
+                if let Some(before) = before {
+                    self.0.execute(
+                        "DELETE FROM tasks WHERE project_uid = ?1 AND uid = ?2",
+                        [&before.project_uid, &before.uid],
+                    )?;
+                }
+                Ok(())
+            }
+            Change::Task {
+                after: Some(after), ..
+            } => write_task(self.0, after),
+            Change::Project { after, .. } => write_project(self.0, after),
+            Change::Batch { changes } => {
+                for change in changes {
+                    self.apply(change)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn log_entries(&self, project_uid: &str) -> Result<Vec<LogEntry>> {
+        let mut stmt = self
+            .0
+            .prepare("SELECT seq, event FROM history WHERE project_uid = ?1 ORDER BY seq")?;
+        let entries = stmt
+            .query_map([project_uid], |row| {
+                Ok(LogEntry {
+                    project_uid: project_uid.to_owned(),
+                    seq: row.get(0)?,
+                    event: row.get::<_, AsJson<_>>(1)?.0,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(entries)
+    }
+
+    fn append(&mut self, project_uid: &str, event: Event) -> Result<u64> {
+        Ok(self.0.query_row(
+            "INSERT INTO history (project_uid, seq, event)
+             SELECT ?1, COALESCE(MAX(seq) + 1, 0), ?2 FROM history WHERE project_uid = ?1
+             RETURNING seq",
+            params![project_uid, AsJson(&event)],
+            |row| row.get(0),
+        )?)
+    }
+
+    fn replace_log(&mut self, project_uid: &str, entries: Vec<LogEntry>) -> Result<()> {
+        self.0
+            .execute("DELETE FROM history WHERE project_uid = ?1", [project_uid])?;
+        let mut stmt = self
+            .0
+            .prepare("INSERT INTO history (project_uid, seq, event) VALUES (?1, ?2, ?3)")?;
+        for entry in entries {
+            stmt.execute(params![entry.project_uid, entry.seq, AsJson(&entry.event)])?;
+        }
+        Ok(())
+    }
+}
+
+/// Upgrade the schema to the current version
+///
+/// An existing database is first copied to `<filename>.v<version>.bak`.
+fn migrate(conn: &mut Connection, filename: &str) -> Result<()> {
+    let version: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if version > MIGRATIONS.len() {
+        bail!(
+            "Database is version {}, but this version of planny only understands up to version {}",
+            version,
+            MIGRATIONS.len()
+        );
+    }
+    if version == MIGRATIONS.len() {
+        return Ok(());
+    }
+
+    let table_count: usize =
+        conn.query_row("SELECT COUNT(*) FROM sqlite_master", [], |row| row.get(0))?;
+    if table_count > 0 {
+        let backup_file = format!("{}.v{}.bak", filename, version);
+        let _ = fs::remove_file(&backup_file);
+        conn.execute("VACUUM INTO ?1", [&backup_file])
+            .with_context(|| format!("Could not back up database to {}", backup_file))?;
+    }
+
+    let tx = conn.transaction()?;
+    for migration in &MIGRATIONS[version..] {
+        match migration {
+            Sql(sql) => tx.execute_batch(sql)?,
+            Rust(f) => f(&tx)?,
+        }
+    }
+    tx.pragma_update(None, "user_version", MIGRATIONS.len())?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Projects used to only store how many people work on them
+fn people_list(tx: &Transaction) -> Result<()> {
+    rewrite_json(tx, "projects", "people", migrations::people_list)?;
+    rewrite_json(tx, "snapshots", "project", migrations::project_people_list)?;
+    rewrite_json(tx, "history", "event", |event| {
+        migrations::for_each_change(event, "project", migrations::project_people_list)
+    })
+}
+
+/// Dependencies used to be a list of uids, tasks themselves keep theirs in a table
+fn dependency_map(tx: &Transaction) -> Result<()> {
+    rewrite_json(tx, "snapshots", "tasks", |tasks| {
+        tasks
+            .as_array_mut()
+            .into_iter()
+            .flatten()
+            .for_each(migrations::task_dependency_map)
+    })?;
+    rewrite_json(tx, "history", "event", |event| {
+        migrations::for_each_change(event, "task", migrations::task_dependency_map)
+    })
+}
+
+/// Rewrite the JSON stored in `column` of every row of `table`
+fn rewrite_json(
+    tx: &Transaction,
+    table: &str,
+    column: &str,
+    f: impl Fn(&mut serde_json::Value),
+) -> Result<()> {
+    let rows = tx
+        .prepare(&format!("SELECT rowid, {} FROM {}", column, table))?
+        .query_map([], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let mut update = tx.prepare(&format!(
+        "UPDATE {} SET {} = ?1 WHERE rowid = ?2",
+        table, column
+    ))?;
+    for (rowid, json) in rows {
+        let mut value = serde_json::from_str(&json)
+            .with_context(|| format!("Could not parse {} of {} {}", column, table, rowid))?;
+        f(&mut value);
+        update.execute(params![value.to_string(), rowid])?;
+    }
+    Ok(())
+}
+
+fn read_project(conn: &Connection, uid: &str) -> Result<Option<Project>> {
+    Ok(conn
+        .query_row(
+            "SELECT * FROM projects WHERE uid = ?1",
+            [uid],
+            project_from_row,
+        )
+        .optional()?)
+}
+
+fn project_from_row(row: &Row) -> rusqlite::Result<Project> {
+    Ok(Project {
+        uid: row.get("uid")?,
+        name: row.get("name")?,
+        people: row.get::<_, AsJson<Vec<Person>>>("people")?.0,
+        // SQLite integers are signed, the seed is stored bit for bit
+        seed: row.get::<_, i64>("seed")? as u64,
+        iterations: row.get("iterations")?,
+        tolerance: row.get("tolerance")?,
+        confidence: row.get("confidence")?,
+        scheduling: row.get::<_, ByName<_>>("scheduling")?.0,
+        efficiency_loss: row.get("efficiency_loss")?,
+        calendar: row.get::<_, AsJson<_>>("calendar")?.0,
+    })
+}
+
+fn write_project(conn: &Connection, project: &Project) -> Result<()> {
+    conn.execute(
+        "INSERT INTO projects (uid, name, people, seed, iterations, tolerance, confidence,
+                               scheduling, efficiency_loss, calendar)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+         ON CONFLICT (uid) DO UPDATE SET
+            name = excluded.name,
+            people = excluded.people,
+            seed = excluded.seed,
+            iterations = excluded.iterations,
+            tolerance = excluded.tolerance,
+            confidence = excluded.confidence,
+            scheduling = excluded.scheduling,
+            efficiency_loss = excluded.efficiency_loss,
+            calendar = excluded.calendar",
+        params![
+            project.uid,
+            project.name,
+            AsJson(&project.people),
+            project.seed as i64,
+            project.iterations,
+            project.tolerance,
+            project.confidence,
+            ByName(&project.scheduling),
+            project.efficiency_loss,
+            AsJson(&project.calendar),
+        ],
+    )?;
+    Ok(())
+}
+
+fn read_pool(conn: &Connection) -> Result<Vec<PoolMember>> {
+    let mut stmt = conn.prepare("SELECT uid, name, capacity FROM pool ORDER BY rowid")?;
+    let pool = stmt
+        .query_map([], |row| {
+            Ok(PoolMember {
+                uid: row.get(0)?,
+                name: row.get(1)?,
+                capacity: row.get(2)?,
+            })
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(pool)
+}
+
+fn snapshot_from_row(row: &Row) -> rusqlite::Result<Snapshot> {
+    Ok(Snapshot {
+        project_uid: row.get("project_uid")?,
+        uid: row.get("uid")?,
+        name: row.get("name")?,
+        created: row.get("created")?,
+        project: row.get::<_, AsJson<_>>("project")?.0,
+        tasks: row.get::<_, AsJson<_>>("tasks")?.0,
+    })
+}
+
+fn read_task(conn: &Connection, project_uid: &str, uid: &str) -> Result<Option<Task>> {
+    let Some(mut task) = conn
+        .query_row(
+            "SELECT * FROM tasks WHERE project_uid = ?1 AND uid = ?2",
+            [project_uid, uid],
+            task_from_row,
+        )
+        .optional()?
+    else {
+        return Ok(None);
+    };
+
+    let mut stmt = conn.prepare(
+        "SELECT dependency_uid, kind, lag, dependency_project_uid
+         FROM dependencies
+         WHERE project_uid = ?1 AND task_uid = ?2",
+    )?;
+    let mut rows = stmt.query([project_uid, uid])?;
+    while let Some(row) = rows.next()? {
+        let dependency = Dependency {
+            kind: row.get::<_, ByName<_>>(1)?.0,
+            lag: row.get(2)?,
+            project: row.get(3)?,
+        };
+        task.dependencies.insert(row.get(0)?, dependency);
+    }
+    Ok(Some(task))
+}
+
+/// Read the tasks of one project, or of all projects
+fn read_tasks(conn: &Connection, project_uid: Option<&str>) -> Result<Vec<Task>> {
+    let mut stmt = conn.prepare("SELECT * FROM tasks WHERE ?1 IS NULL OR project_uid = ?1")?;
+    let mut tasks: HashMap<(String, String), Task> = stmt
+        .query_map([project_uid], task_from_row)?
+        .map(|t| t.map(|t| ((t.project_uid.clone(), t.uid.clone()), t)))
+        .collect::<rusqlite::Result<_>>()?;
+
+    let mut stmt = conn.prepare(
+        "SELECT project_uid, task_uid, dependency_uid, kind, lag, dependency_project_uid
+         FROM dependencies
+         WHERE ?1 IS NULL OR project_uid = ?1",
+    )?;
+    let mut rows = stmt.query([project_uid])?;
+    while let Some(row) = rows.next()? {
+        if let Some(task) = tasks.get_mut(&(row.get(0)?, row.get(1)?)) {
+            let dependency = Dependency {
+                kind: row.get::<_, ByName<_>>(3)?.0,
+                lag: row.get(4)?,
+                project: row.get(5)?,
+            };
+            task.dependencies.insert(row.get(2)?, dependency);
+        }
+    }
+
+    Ok(tasks.into_values().collect())
+}
+
+fn task_from_row(row: &Row) -> rusqlite::Result<Task> {
+    Ok(Task {
+        project_uid: row.get("project_uid")?,
+        uid: row.get("uid")?,
+        r#type: row.get::<_, ByName<_>>("type")?.0,
+        id: row.get("id")?,
+        title: row.get("title")?,
+        estimate: row.get("estimate")?,
+        risk: row.get::<_, Option<ByName<_>>>("risk")?.map(|r| r.0),
+        distribution: row.get::<_, ByName<_>>("distribution")?.0,
+        optimistic: row.get("optimistic")?,
+        pessimistic: row.get("pessimistic")?,
+        sigma: row.get("sigma")?,
+        dependencies: Default::default(),
+        skills: row.get::<_, AsJson<_>>("skills")?.0,
+        assignee: row.get("assignee")?,
+        status: row.get::<_, ByName<_>>("status")?.0,
+        started: row.get("started")?,
+        finished: row.get("finished")?,
+        remaining: row.get("remaining")?,
+        deadline: row.get::<_, Option<AsJson<_>>>("deadline")?.map(|d| d.0),
+        parent: row.get("parent")?,
+        priority: row.get("priority")?,
+        max_people: row.get("max_people")?,
+    })
+}
+
+fn write_task(conn: &Connection, task: &Task) -> Result<()> {
+    conn.execute(
+        "INSERT INTO tasks (project_uid, uid, type, id, title, estimate, risk, skills, assignee,
+                            status, started, finished, remaining,
+                            distribution, optimistic, pessimistic, sigma, deadline, parent, priority,
+                            max_people)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18,
+                 ?19, ?20, ?21)
+         ON CONFLICT (project_uid, uid) DO UPDATE SET
+            type = excluded.type,
+            id = excluded.id,
+            title = excluded.title,
+            estimate = excluded.estimate,
+            risk = excluded.risk,
+            skills = excluded.skills,
+            assignee = excluded.assignee,
+            status = excluded.status,
+            started = excluded.started,
+            finished = excluded.finished,
+            remaining = excluded.remaining,
+            distribution = excluded.distribution,
+            optimistic = excluded.optimistic,
+            pessimistic = excluded.pessimistic,
+            sigma = excluded.sigma,
+            deadline = excluded.deadline,
+            parent = excluded.parent,
+            priority = excluded.priority,
+            max_people = excluded.max_people",
+        params![
+            task.project_uid,
+            task.uid,
+            ByName(&task.r#type),
+            task.id,
+            task.title,
+            task.estimate,
+            task.risk.as_ref().map(ByName),
+            AsJson(&task.skills),
+            task.assignee,
+            ByName(&task.status),
+            task.started,
+            task.finished,
+            task.remaining,
+            ByName(&task.distribution),
+            task.optimistic,
+            task.pessimistic,
+            task.sigma,
+            task.deadline.as_ref().map(AsJson),
+            task.parent,
+            task.priority,
+            task.max_people,
+        ],
+    )?;
+
+    conn.execute(
+        "DELETE FROM dependencies WHERE project_uid = ?1 AND task_uid = ?2",
+        [&task.project_uid, &task.uid],
+    )?;
+    let mut stmt = conn.prepare(
+        "INSERT INTO dependencies (project_uid, task_uid, dependency_uid, kind, lag,
+                                   dependency_project_uid)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+    )?;
+    for (uid, dependency) in &task.dependencies {
+        stmt.execute(params![
+            task.project_uid,
+            task.uid,
+            uid,
+            ByName(&dependency.kind),
+            dependency.lag,
+            dependency.project,
+        ])?;
+    }
+    Ok(())
+}
+
+/// Stores a unit enum as the name it serializes to
+struct ByName<T>(T);
+
+impl<T: Serialize> ToSql for ByName<T> {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        match serde_json::to_value(&self.0) {
+            Ok(serde_json::Value::String(s)) => Ok(ToSqlOutput::from(s)),
+            Ok(v) => Err(rusqlite::Error::ToSqlConversionFailure(
+                format!("not a unit enum: {v}").into(),
+            )),
+            Err(e) => Err(rusqlite::Error::ToSqlConversionFailure(Box::new(e))),
+        }
+    }
+}
+
+impl<T: DeserializeOwned> FromSql for ByName<T> {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let name = value.as_str()?;
+        serde_json::from_value(serde_json::Value::String(name.to_owned()))
+            .map(ByName)
+            .map_err(|e| FromSqlError::Other(Box::new(e)))
+    }
+}
+
+/// Stores a structured value as JSON text
+struct AsJson<T>(T);
+
+impl<T: Serialize> ToSql for AsJson<T> {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        serde_json::to_string(&self.0)
+            .map(ToSqlOutput::from)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))
+    }
+}
+
+impl<T: DeserializeOwned> FromSql for AsJson<T> {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        serde_json::from_str(value.as_str()?)
+            .map(AsJson)
+            .map_err(|e| FromSqlError::Other(Box::new(e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_new_database_gets_the_current_schema() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn, ":memory:").unwrap();
+        let version: usize = conn
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+    }
+
+    #[test]
+    fn rust_migrations_rewrite_the_stored_json() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            r#"
+CREATE TABLE projects (people TEXT);
+CREATE TABLE snapshots (project TEXT, tasks TEXT);
+CREATE TABLE history (event TEXT);
+INSERT INTO projects VALUES ('2');
+INSERT INTO snapshots VALUES ('{"people": 1}', '[{"dependencies": ["a", "b"]}]');
+INSERT INTO history VALUES
+    ('{"change": {"kind": "project", "before": {"people": 3}, "after": {"people": 1}}}'),
+    ('{"change": {"kind": "task", "before": null, "after": {"dependencies": ["a"]}}}');
+"#,
+        )
+        .unwrap();
+        let tx = conn.transaction().unwrap();
+        people_list(&tx).unwrap();
+        dependency_map(&tx).unwrap();
+
+        let json = |sql: &str| -> Vec<serde_json::Value> {
+            tx.prepare(sql)
+                .unwrap()
+                .query_map([], |row| row.get::<_, String>(0))
+                .unwrap()
+                .map(|s| serde_json::from_str(&s.unwrap()).unwrap())
+                .collect()
+        };
+        let people = json("SELECT people FROM projects");
+        assert_eq!(people[0].as_array().unwrap().len(), 2);
+        assert_eq!(people[0][1]["uid"], "person-2");
+        assert_eq!(
+            json("SELECT project FROM snapshots")[0]["people"][0]["name"],
+            "Person 1"
+        );
+        let tasks = json("SELECT tasks FROM snapshots");
+        assert_eq!(tasks[0][0]["dependencies"]["b"]["kind"], "fs");
+        let history = json("SELECT event FROM history");
+        assert_eq!(
+            history[0]["change"]["before"]["people"]
+                .as_array()
+                .unwrap()
+                .len(),
+            3
+        );
+        assert_eq!(
+            history[1]["change"]["after"]["dependencies"]["a"]["lag"],
+            0.0
+        );
+    }
+}
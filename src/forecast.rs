@@ -1,96 +1,830 @@
+use chrono::NaiveDate;
 use itertools::Itertools;
 use ordered_float::OrderedFloat;
-use rand::seq::IteratorRandom;
-use rand_distr::{Distribution, LogNormal}; // 0.7.2
-use std::{collections::HashMap, ops::Range};
+use rand::{rngs::StdRng, seq::IteratorRandom, RngCore, SeedableRng};
+use rand_distr::{Distribution as _, LogNormal, Pert, Triangular}; // 0.7.2
+use std::{cmp::Reverse, collections::HashMap, ops::Range, slice, thread};
 
-use quantogram::Quantogram;
+use quantogram::{Quantogram, QuantogramBuilder};
 
 use crate::{
-    datamodel::{Risk, Task, TaskType},
+    calendar::Calendar,
+    datamodel::{
+        self, ancestors, inherited_dependencies, Dependency, DependencyKind, Distribution, Person,
+        Project, Risk, Scheduling, Task, TaskStatus, TaskType,
+    },
     topo_queue::TopoQueue,
 };
 
-const N: u32 = 1000;
+/// Iterations are simulated in chunks, each with its own rng, so the results
+/// don't depend on how many threads there are
+const CHUNK: u32 = 100;
+
+/// More than this takes too long to wait for
+pub const MAX_ITERATIONS: u32 = 1_000_000;
+
+/// When converging, the estimates are compared after every round of iterations
+const ROUND: u32 = 1000;
 
 pub struct SimulationResults {
     pub task_stats: HashMap<String, Range<Quantogram>>,
+    /// How many times the project was simulated
+    pub iterations: u32,
+    /// Tasks that nobody qualifies for, which anyone was booked for instead
+    pub unstaffed: Vec<String>,
+    /// Per task, the fraction of iterations it was on the critical path
+    pub criticality: HashMap<String, f64>,
+    /// Per task, how its sampled duration correlates with the finish of the project, -1 to 1
+    pub sensitivity: HashMap<String, f64>,
+    /// Per milestone with a deadline, the fraction of iterations it was reached by then
+    pub deadline_chance: HashMap<String, f64>,
 }
 
-pub fn simulate_tasks(tasks: impl Iterator<Item = Task>, people: u32) -> SimulationResults {
-    let tasks: HashMap<String, Task> = HashMap::from_iter(tasks.map(|t| (t.uid.clone(), t)));
-    let mut stats: HashMap<String, Range<Quantogram>> = HashMap::from_iter(
-        tasks
-            .keys()
-            .map(|k| (k.clone(), Quantogram::new()..Quantogram::new())),
-    );
+/// Simulate the rest of a project `project.iterations` times, spread over all cores
+///
+/// Tasks that are done keep their actual dates, and tasks in progress only
+/// sample the work that is left, starting `today`.
+///
+/// With a `project.tolerance` it stops early, once the p50 and p90 finish of
+/// every task move less than that fraction from one round to the next.
+///
+/// The same tasks, project and day always give the same results.
+pub fn simulate_tasks(
+    tasks: impl Iterator<Item = Task>,
+    project: &Project,
+    today: NaiveDate,
+) -> SimulationResults {
+    simulate_projects(tasks, slice::from_ref(project), true, None, today)
+}
+
+/// Simulate the tasks of several projects together, on the calendar and with the settings of
+/// the first one
+///
+/// Task uids have to be unique across the projects, see `portfolio::qualified`. With
+/// `shared_people` anyone on any of the projects may work on any task, otherwise people only
+/// work on their own project. No work is planned before the start date of its project.
+///
+/// With a `focus` project, criticality and sensitivity are about when that project finishes,
+/// rather than when the last of all tasks does.
+pub fn simulate_projects(
+    tasks: impl Iterator<Item = Task>,
+    projects: &[Project],
+    shared_people: bool,
+    focus: Option<&str>,
+    today: NaiveDate,
+) -> SimulationResults {
+    let project = &projects[0];
+    let simulation = Simulation::new(tasks, projects, shared_people, focus, today);
+    if simulation.tasks.is_empty() {
+        return SimulationResults {
+            task_stats: HashMap::new(),
+            iterations: 0,
+            unstaffed: vec![],
+            criticality: HashMap::new(),
+            sensitivity: HashMap::new(),
+            deadline_chance: HashMap::new(),
+        };
+    }
+    let mut stats: Vec<Range<Quantogram>> = simulation
+        .tasks
+        .iter()
+        .map(|_| new_quantogram()..new_quantogram())
+        .collect();
 
-    let mut rng = rand::thread_rng();
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let mut seeds = StdRng::seed_from_u64(project.seed);
+    let max_iterations = project.iterations.clamp(1, MAX_ITERATIONS);
+    let mut iterations = 0;
+    let mut critical = vec![0_u32; simulation.tasks.len()];
+    let mut correlations = vec![Correlation::default(); simulation.tasks.len()];
+    let mut on_time = vec![0_u32; simulation.tasks.len()];
+    let mut previous: Option<Vec<(f64, f64)>> = None;
+    while iterations < max_iterations {
+        let count = ROUND.min(max_iterations - iterations);
+        let chunks: Vec<(u64, u32)> = (0..count)
+            .step_by(CHUNK as usize)
+            .map(|i| (seeds.next_u64(), CHUNK.min(count - i)))
+            .collect();
 
-    let start_queue = queue_from_tasks(tasks.values());
-    for _ in 0..N {
-        // FIXME: Parallellism between peeps
+        // Quantogram can't be merged, so the threads return their samples,
+        // and they are added here in the same order every time
+        for samples in simulation.run_chunks(&chunks, threads) {
+            for iteration in samples.chunks(simulation.tasks.len()) {
+                let finish = iteration
+                    .iter()
+                    .zip(&simulation.counts)
+                    .filter(|(_, counts)| **counts)
+                    .filter_map(|(s, _)| s.as_ref())
+                    .map(|s| s.interval.end)
+                    .fold(0.0, f64::max);
+                for (i, sample) in iteration.iter().enumerate() {
+                    if let Some(sample) = sample {
+                        stats[i].start.add(sample.interval.start);
+                        stats[i].end.add(sample.interval.end);
+                        critical[i] += sample.critical as u32;
+                        correlations[i].add(sample.duration, finish);
+                        if simulation.deadlines[i].is_some_and(|d| sample.interval.end <= d) {
+                            on_time[i] += 1;
+                        }
+                    }
+                }
+            }
+        }
+        iterations += count;
 
-        let mut plan: HashMap<String, Range<f64>> = HashMap::new();
-        let mut queue = start_queue.clone();
-        let mut people = PeopleAllocation::new(people);
+        if let Some(tolerance) = project.tolerance {
+            let current: Vec<(f64, f64)> = stats
+                .iter()
+                .map(|s| {
+                    (
+                        s.end.quantile(0.5).unwrap_or_default(),
+                        s.end.quantile(0.9).unwrap_or_default(),
+                    )
+                })
+                .collect();
+            if previous.is_some_and(|p| settled(&p, &current, tolerance)) {
+                break;
+            }
+            previous = Some(current);
+        }
+    }
+
+    SimulationResults {
+        task_stats: simulation
+            .tasks
+            .iter()
+            .map(|t| t.uid.clone())
+            .zip(stats)
+            .collect(),
+        iterations,
+        unstaffed: simulation
+            .tasks
+            .iter()
+            .zip(&simulation.qualified)
+            .filter(|(_, q)| q.is_none())
+            .map(|(t, _)| t.uid.clone())
+            .collect(),
+        criticality: simulation
+            .tasks
+            .iter()
+            .zip(critical)
+            .map(|(t, c)| (t.uid.clone(), c as f64 / iterations as f64))
+            .collect(),
+        sensitivity: simulation
+            .tasks
+            .iter()
+            .zip(correlations)
+            .map(|(t, c)| (t.uid.clone(), c.coefficient()))
+            .collect(),
+        deadline_chance: simulation
+            .tasks
+            .iter()
+            .zip(&simulation.deadlines)
+            .zip(on_time)
+            .filter(|((_, d), _)| d.is_some())
+            .map(|((t, _), n)| (t.uid.clone(), n as f64 / iterations as f64))
+            .collect(),
+    }
+}
+
+/// Pearson correlation, accumulated one pair at a time with Welford's method
+#[derive(Debug, Clone, Default)]
+struct Correlation {
+    n: f64,
+    mean_x: f64,
+    mean_y: f64,
+    m2_x: f64,
+    m2_y: f64,
+    co_moment: f64,
+}
+
+impl Correlation {
+    fn add(&mut self, x: f64, y: f64) {
+        self.n += 1.0;
+        let dx = x - self.mean_x;
+        let dy = y - self.mean_y;
+        self.mean_x += dx / self.n;
+        self.mean_y += dy / self.n;
+        self.m2_x += dx * (x - self.mean_x);
+        self.m2_y += dy * (y - self.mean_y);
+        self.co_moment += dx * (y - self.mean_y);
+    }
+
+    /// 0 when either side never changes
+    fn coefficient(&self) -> f64 {
+        if self.m2_x <= f64::EPSILON || self.m2_y <= f64::EPSILON {
+            return 0.0;
+        }
+        (self.co_moment / (self.m2_x * self.m2_y).sqrt()).clamp(-1.0, 1.0)
+    }
+}
+
+/// The default Quantogram is only accurate to 1%, too coarse to see estimates settle
+fn new_quantogram() -> Quantogram {
+    QuantogramBuilder::new().with_error(0.001).build()
+}
+
+/// Whether no estimate moved more than `tolerance`, relative to its previous value
+fn settled(previous: &[(f64, f64)], current: &[(f64, f64)], tolerance: f64) -> bool {
+    let close = |a: f64, b: f64| (a - b).abs() <= tolerance * a.abs().max(f64::EPSILON);
+    previous
+        .iter()
+        .zip(current)
+        .all(|(p, c)| close(p.0, c.0) && close(p.1, c.1))
+}
+
+/// Everything that stays the same between iterations
+struct Simulation {
+    /// Ordered by uid, the samples of an iteration are in the same order
+    tasks: Vec<Task>,
+    by_uid: HashMap<String, usize>,
+    /// Per task, the tasks it depends on and how, including what its epics depend on
+    dependencies: Vec<Vec<(usize, Dependency)>>,
+    /// Per epic, its subtasks, which it spans instead of being worked on itself
+    children: Vec<Vec<usize>>,
+    start_queue: TopoQueue,
+    /// Nobody booked yet
+    people: PeopleAllocation,
+    /// Per task, who can work on it, `None` if nobody qualifies
+    qualified: Vec<Option<Vec<usize>>>,
+    /// Per task, how many people can work on it at once, at least one
+    max_people: Vec<usize>,
+    /// Work added for every extra person on a task, as a fraction of the task
+    efficiency_loss: f64,
+    /// Per task, when it actually happened if it is done
+    done: Vec<Option<Interval>>,
+    /// Per task, when it actually started if it is in progress
+    started: Vec<Option<f64>>,
+    /// Today, no work is planned before it
+    now: f64,
+    /// Per task, today or the start of its project if that is later
+    not_before: Vec<f64>,
+    /// Per task, whether its finish counts as the end, for the critical path and sensitivity
+    counts: Vec<bool>,
+    /// Per milestone, how many working days into the project its deadline falls
+    deadlines: Vec<Option<f64>>,
+    durations: Vec<TaskDuration>,
+}
+
+impl Simulation {
+    fn new(
+        tasks: impl Iterator<Item = Task>,
+        projects: &[Project],
+        shared_people: bool,
+        focus: Option<&str>,
+        today: NaiveDate,
+    ) -> Self {
+        let project = &projects[0];
+        let tasks = tasks.sorted_by(|a, b| a.uid.cmp(&b.uid)).collect_vec();
+        let by_uid: HashMap<String, usize> = tasks
+            .iter()
+            .enumerate()
+            .map(|(i, t)| (t.uid.clone(), i))
+            .collect();
+        let tasks_by_uid: HashMap<&str, &Task> =
+            HashMap::from_iter(tasks.iter().map(|t| (t.uid.as_str(), t)));
+        let subtasks = datamodel::children(&tasks_by_uid);
+        let children = tasks
+            .iter()
+            .map(|task| {
+                subtasks
+                    .get(task.uid.as_str())
+                    .into_iter()
+                    .flatten()
+                    .map(|uid| by_uid[*uid])
+                    .sorted()
+                    .collect_vec()
+            })
+            .collect_vec();
+        let dependencies = tasks
+            .iter()
+            .map(|task| {
+                inherited_dependencies(&tasks_by_uid, task)
+                    .into_iter()
+                    .filter_map(|(uid, dependency)| Some((*by_uid.get(uid)?, dependency)))
+                    .sorted_by_key(|(d, _)| *d)
+                    .collect_vec()
+            })
+            .collect_vec();
+        // Epics wait for their subtasks, so they can span them
+        let start_queue = TopoQueue::from_iter(tasks.iter().enumerate().map(|(i, task)| {
+            let waits_for = dependencies[i]
+                .iter()
+                .map(|(d, _)| *d)
+                .chain(children[i].iter().copied());
+            (
+                task.uid.clone(),
+                waits_for.map(|d| tasks[d].uid.clone()).collect(),
+            )
+        }));
+
+        // Without a start date, the project is forecast from today on
+        let calendar = &project.calendar;
+        let now = calendar.offset(today).unwrap_or(0.0);
+        // An epic is done when its subtasks are
+        let done: Vec<Option<Interval>> = tasks
+            .iter()
+            .map(|task| {
+                let epic = subtasks.contains_key(task.uid.as_str());
+                (task.status == TaskStatus::Done && !epic).then(|| {
+                    let end = task
+                        .finished
+                        .and_then(|d| calendar.offset(d.succ_opt()?))
+                        .unwrap_or(now);
+                    let start = task.started.and_then(|d| calendar.offset(d)).unwrap_or(end);
+                    start.min(end)..end
+                })
+            })
+            .collect();
+        let started: Vec<Option<f64>> = tasks
+            .iter()
+            .map(|task| match task.status {
+                TaskStatus::InProgress => Some(
+                    task.started
+                        .and_then(|d| calendar.offset(d))
+                        .unwrap_or(now)
+                        .min(now),
+                ),
+                _ => None,
+            })
+            .collect();
+
+        // The project each person is on
+        let (mut people, mut teams): (Vec<Person>, Vec<&str>) = projects
+            .iter()
+            .flat_map(|p| p.people.iter().map(move |person| (person, p.uid.as_str())))
+            .filter(|(p, _)| p.capacity > 0.0 && p.capacity.is_finite())
+            .map(|(person, team)| (person.clone(), team))
+            .unzip();
+        // Someone has to do the work
+        if people.is_empty() {
+            people.push(Person::default());
+            teams.push(&project.uid);
+        }
+        let qualified = tasks
+            .iter()
+            .map(|task| {
+                let epic = subtasks.contains_key(task.uid.as_str());
+                let who = (0..people.len())
+                    .filter(|i| {
+                        epic || task.r#type == TaskType::Milestone
+                            || task.status == TaskStatus::Done
+                            || (shared_people || teams[*i] == task.project_uid)
+                                && people[*i].qualifies_for(task)
+                    })
+                    .collect_vec();
+                (!who.is_empty()).then_some(who)
+            })
+            .collect();
+
+        // Lowest rank gets picked up first, ties at random
+        let durations = tasks.iter().map(TaskDuration::new).collect_vec();
+        let ranks = match project.scheduling {
+            Scheduling::Random => vec![0.0; tasks.len()],
+            Scheduling::Priority => tasks
+                .iter()
+                .map(|task| {
+                    let epics = ancestors(&tasks_by_uid, task).into_iter();
+                    let priority = std::iter::once(task)
+                        .chain(epics.map(|uid| tasks_by_uid[uid]))
+                        .find_map(|t| t.priority);
+                    priority.map_or(f64::INFINITY, f64::from)
+                })
+                .collect(),
+            Scheduling::LongestPath => {
+                let order = topological_order(start_queue.clone(), &by_uid);
+                let paths = remaining_paths(&order, &dependencies, &children, &durations);
+                paths.into_iter().map(|p| -p).collect()
+            }
+            Scheduling::ShortestTask => durations.iter().map(TaskDuration::mean).collect(),
+        };
+        // Work that is going on comes first, unless any task can be picked
+        let start_queue = match project.scheduling {
+            Scheduling::Random => start_queue,
+            _ => start_queue.with_ranks(
+                tasks
+                    .iter()
+                    .zip(ranks)
+                    .enumerate()
+                    .map(|(i, (task, rank))| {
+                        let going = done[i].is_some() || started[i].is_some();
+                        (
+                            task.uid.clone(),
+                            if going { f64::NEG_INFINITY } else { rank },
+                        )
+                    })
+                    .collect(),
+            ),
+        };
+
+        let starts: HashMap<&str, f64> = projects
+            .iter()
+            .filter_map(|p| Some((p.uid.as_str(), calendar.offset(p.calendar.start?)?)))
+            .collect();
+        let not_before = tasks
+            .iter()
+            .map(|t| {
+                starts
+                    .get(t.project_uid.as_str())
+                    .map_or(now, |s| s.max(now))
+            })
+            .collect();
+        let max_people = tasks
+            .iter()
+            .map(|t| t.max_people.map_or(1, |m| m.max(1) as usize))
+            .collect();
+        let counts = tasks
+            .iter()
+            .map(|t| focus.is_none_or(|f| t.project_uid == f))
+            .collect();
+
+        // A date can only be counted from a start date
+        let deadlines = tasks
+            .iter()
+            .map(|t| {
+                t.deadline
+                    .filter(|_| t.r#type == TaskType::Milestone)?
+                    .offset(calendar)
+            })
+            .collect();
+
+        Simulation {
+            by_uid,
+            dependencies,
+            children,
+            start_queue,
+            durations,
+            tasks,
+            people: PeopleAllocation::new(&people, calendar),
+            qualified,
+            max_people,
+            efficiency_loss: project.efficiency_loss.max(0.0),
+            done,
+            started,
+            now,
+            not_before,
+            counts,
+            deadlines,
+        }
+    }
+
+    /// Run chunks of `(seed, iterations)` on up to `threads` threads, results in the order of the chunks
+    ///
+    /// Every chunk returns one sample per task per iteration, `None` if the
+    /// task couldn't be scheduled.
+    fn run_chunks(&self, chunks: &[(u64, u32)], threads: usize) -> Vec<Vec<Option<Sample>>> {
+        let threads = threads.clamp(1, chunks.len().max(1));
+        let mut results: Vec<(usize, Vec<Option<Sample>>)> = thread::scope(|scope| {
+            let handles = (0..threads)
+                .map(|t| {
+                    scope.spawn(move || {
+                        chunks
+                            .iter()
+                            .enumerate()
+                            .skip(t)
+                            .step_by(threads)
+                            .map(|(i, (seed, count))| {
+                                let mut rng = StdRng::seed_from_u64(*seed);
+                                let samples = (0..*count)
+                                    .flat_map(|_| self.run_once(&mut rng))
+                                    .collect_vec();
+                                (i, samples)
+                            })
+                            .collect_vec()
+                    })
+                })
+                .collect_vec();
+            handles
+                .into_iter()
+                .flat_map(|h| h.join().expect("Simulation thread panicked"))
+                .collect()
+        });
+        results.sort_by_key(|(i, _)| *i);
+        results.into_iter().map(|(_, samples)| samples).collect()
+    }
+
+    /// Simulate the project once
+    fn run_once(&self, rng: &mut StdRng) -> Vec<Option<Sample>> {
+        let mut plan: Vec<Option<Interval>> = vec![None; self.tasks.len()];
+        // The task that kept each task from starting earlier, if any
+        let mut drivers: Vec<Option<usize>> = vec![None; self.tasks.len()];
+        let mut durations: Vec<f64> = vec![0.0; self.tasks.len()];
+        // The task each person worked on last
+        let mut last_task: Vec<Option<usize>> = vec![None; self.people.capacities.len()];
+        let mut queue = self.start_queue.clone();
+        let mut people = self.people.clone();
 
         while !queue.is_empty() {
-            let Some(task) = queue
-                .available()
-                .choose(&mut rng)
-                .and_then(|t| tasks.get(t))
+            let Some(&i) = queue
+                .next_available()
+                .choose(rng)
+                .and_then(|t| self.by_uid.get(t))
             else {
                 break;
             };
+            let task = &self.tasks[i];
+            queue.remove(&task.uid);
 
-            let duration = if task.r#type == TaskType::Milestone {
-                0.0
-            } else {
-                // Based in this guy's musings:
-                // https://erikbern.com/2019/04/15/why-software-projects-take-longer-than-you-think-a-statistical-model.html
-                let estimated_duration = task.estimate.unwrap_or(1.0);
-
-                let sigma = match task.risk.as_ref().copied().unwrap_or_default() {
-                    Risk::Low => 0.25,
-                    Risk::Medium => 0.5,
-                    Risk::High => 1.0,
-                };
+            // An epic runs from the first of its subtasks to start to the last to finish
+            if !self.children[i].is_empty() {
+                let spanned = self.children[i]
+                    .iter()
+                    .filter_map(|c| Some((*c, plan[*c].as_ref()?)))
+                    .collect_vec();
+                let start = spanned.iter().map(|(_, p)| p.start).reduce(f64::min);
+                let last = spanned
+                    .iter()
+                    .max_by_key(|(c, p)| (OrderedFloat(p.end), *c))
+                    .map(|(c, p)| (*c, p.end));
+                if let (Some(start), Some((last, end))) = (start, last) {
+                    plan[i] = Some(start..end);
+                    durations[i] = end - start;
+                    drivers[i] = Some(last).filter(|c| self.done[*c].is_none() && end > self.now);
+                }
+                continue;
+            }
 
-                let log_normal = LogNormal::from_mean_cv(1.0, sigma).unwrap();
+            // Done is done, nobody works on it anymore
+            if let Some(done) = &self.done[i] {
+                plan[i] = Some(done.clone());
+                continue;
+            }
 
-                let blowup = log_normal.sample(&mut rand::thread_rng());
-                estimated_duration * blowup
-            };
+            let duration = self.durations[i].sample(rng);
+            durations[i] = duration;
 
-            // Start time is the max of the end time of all dependencies
-            let dependencies_end = task
-                .dependencies
+            // Every dependency holds up either the start or the finish of the
+            // task, work that has already started goes on regardless
+            let bounds = match self.started[i] {
+                Some(_) => vec![],
+                None => self.dependencies[i]
+                    .iter()
+                    .filter_map(|(d, dependency)| {
+                        Some((*d, Bound::new(plan[*d].as_ref()?, dependency)))
+                    })
+                    .collect_vec(),
+            };
+            let start_bound = bounds
                 .iter()
-                .map(|id| plan[id].end)
-                .fold(0.0_f64, |a, b| a.max(b));
+                .filter_map(|(_, b)| b.start())
+                .fold(self.not_before[i], f64::max);
+            let finish_bound = bounds
+                .iter()
+                .filter_map(|(_, b)| b.finish())
+                .reduce(f64::max);
 
             // Find personnel to carry out this task (FIXME: randomize?)
-            let (person, start) = people.availabilities(dependencies_end).next().unwrap();
+            let mut earliest = finish_bound.map_or(start_bound, |f| start_bound.max(f - duration));
+            let (team, interval) = loop {
+                let team = people.team(
+                    self.qualified[i].as_deref(),
+                    earliest,
+                    duration,
+                    self.max_people[i],
+                    self.efficiency_loss,
+                );
+                let interval = span(&team);
+                // Someone faster than full time would finish too early, start later
+                match finish_bound {
+                    Some(f) if interval.end < f - 1e-9 => earliest += f - interval.end,
+                    _ => break (team, interval),
+                }
+            };
 
-            let end = start + duration;
+            // Whatever held the task up right until it started, or finished,
+            // held it up, unless that was in the past
+            let held_up_by = |d: usize, at: f64| self.done[d].is_none() && at > self.now;
+            let last_dependency = bounds
+                .iter()
+                .filter(|(d, b)| match b {
+                    Bound::Start(t) => held_up_by(*d, *t) && (t - interval.start).abs() < 1e-9,
+                    Bound::Finish(t) => held_up_by(*d, *t) && (t - interval.end).abs() < 1e-9,
+                })
+                .max_by_key(|(d, b)| (OrderedFloat(b.time()), *d))
+                .map(|(d, _)| *d);
+            let ended_at_start = |d: &usize| {
+                plan[*d]
+                    .as_ref()
+                    .is_some_and(|p| held_up_by(*d, p.end) && (p.end - interval.start).abs() < 1e-9)
+            };
+            let last_of_team = team
+                .iter()
+                .filter_map(|(person, _)| last_task[*person])
+                .find(ended_at_start);
+            drivers[i] = last_dependency.or(last_of_team);
+            for (person, booked) in team {
+                last_task[person] = Some(i);
+                people.book(person, booked);
+            }
+            plan[i] = Some(match self.started[i] {
+                Some(started) => started..interval.end,
+                None => interval,
+            });
+        }
 
-            people.book(person, start..end);
-            plan.insert(task.uid.clone(), start..end);
+        // Walk back from whatever finished last along what held each task up
+        let mut critical = vec![false; self.tasks.len()];
+        let end = plan
+            .iter()
+            .zip(&self.counts)
+            .filter(|(_, counts)| **counts)
+            .filter_map(|(p, _)| p.as_ref())
+            .map(|p| p.end)
+            .fold(self.now, f64::max);
+        for (last, p) in plan.iter().enumerate() {
+            if !self.counts[last]
+                || self.done[last].is_some()
+                || !p.as_ref().is_some_and(|p| (p.end - end).abs() < 1e-9)
+            {
+                continue;
+            }
+            let mut next = Some(last);
+            while let Some(i) = next.filter(|i| !critical[*i]) {
+                critical[i] = true;
+                next = drivers[i];
+            }
+        }
 
-            queue.remove(&task.uid);
+        plan.into_iter()
+            .zip(critical)
+            .zip(durations)
+            .map(|((p, critical), duration)| {
+                Some(Sample {
+                    interval: p?,
+                    duration,
+                    critical,
+                })
+            })
+            .collect()
+    }
+}
+
+/// When a dependency lets a task start or finish at the earliest
+#[derive(Debug, Clone, Copy)]
+enum Bound {
+    Start(f64),
+    Finish(f64),
+}
+
+impl Bound {
+    fn new(plan: &Interval, dependency: &Dependency) -> Self {
+        match dependency.kind {
+            DependencyKind::FinishToStart => Bound::Start(plan.end + dependency.lag),
+            DependencyKind::StartToStart => Bound::Start(plan.start + dependency.lag),
+            DependencyKind::FinishToFinish => Bound::Finish(plan.end + dependency.lag),
+        }
+    }
+
+    fn start(&self) -> Option<f64> {
+        match self {
+            Bound::Start(t) => Some(*t),
+            Bound::Finish(_) => None,
+        }
+    }
+
+    fn finish(&self) -> Option<f64> {
+        match self {
+            Bound::Start(_) => None,
+            Bound::Finish(t) => Some(*t),
+        }
+    }
+
+    fn time(&self) -> f64 {
+        match self {
+            Bound::Start(t) | Bound::Finish(t) => *t,
+        }
+    }
+}
+
+/// One task in one iteration
+#[derive(Debug, Clone)]
+struct Sample {
+    interval: Interval,
+    /// The sampled work, without waiting for people
+    duration: f64,
+    /// Whether the task held up the end of the project
+    critical: bool,
+}
+
+/// Task indices in an order where everything comes after what it waits for
+fn topological_order(mut queue: TopoQueue, by_uid: &HashMap<String, usize>) -> Vec<usize> {
+    let mut order = vec![];
+    loop {
+        let Some(uid) = queue.available().next().cloned() else {
+            break;
+        };
+        queue.remove(&uid);
+        order.push(by_uid[&uid]);
+    }
+    order
+}
 
-            let stats = stats.get_mut(&task.uid).unwrap();
-            stats.start.add(start);
-            stats.end.add(end);
+/// Per task, the expected days from its start until everything that waits for it is done
+///
+/// Epics count as the moment their last subtask finishes.
+fn remaining_paths(
+    order: &[usize],
+    dependencies: &[Vec<(usize, Dependency)>],
+    children: &[Vec<usize>],
+    durations: &[TaskDuration],
+) -> Vec<f64> {
+    let expected = |i: usize| match children[i].is_empty() {
+        true => durations[i].mean(),
+        false => 0.0,
+    };
+    let mut paths = (0..durations.len()).map(expected).collect_vec();
+    // Everything waiting for a task comes later, so its path is known by then
+    for &i in order.iter().rev() {
+        for (d, dependency) in &dependencies[i] {
+            let until_start = match dependency.kind {
+                DependencyKind::FinishToStart => expected(*d) + dependency.lag,
+                DependencyKind::StartToStart => dependency.lag,
+                DependencyKind::FinishToFinish => expected(*d) + dependency.lag - expected(i),
+            };
+            paths[*d] = paths[*d].max(until_start + paths[i]);
+        }
+        for &c in &children[i] {
+            paths[c] = paths[c].max(expected(c) + paths[i]);
         }
     }
+    paths
+}
 
-    SimulationResults { task_stats: stats }
+/// How long a task takes, ready to be sampled
+#[derive(Debug, Clone)]
+enum TaskDuration {
+    Fixed(f64),
+    /// The estimate, times a blowup factor
+    LogNormal(f64, LogNormal<f64>),
+    /// The mean, and the distribution
+    Pert(f64, Pert<f64>),
+    Triangular(f64, Triangular<f64>),
 }
 
-pub fn queue_from_tasks<'a>(tasks: impl Iterator<Item = &'a Task>) -> TopoQueue {
-    TopoQueue::from_iter(tasks.map(|t| (t.uid.clone(), t.dependencies.clone())))
+impl TaskDuration {
+    fn new(task: &Task) -> Self {
+        if task.r#type == TaskType::Milestone {
+            return TaskDuration::Fixed(0.0);
+        }
+
+        // Only the work that is left is uncertain, and the bounds are for the whole task
+        let remaining = match task.status {
+            TaskStatus::InProgress => task.remaining,
+            _ => None,
+        };
+        let estimate = remaining.or(task.estimate).unwrap_or(1.0);
+        let bounds = task
+            .optimistic
+            .zip(task.pessimistic)
+            .filter(|_| remaining.is_none() && task.estimate.is_some())
+            .filter(|(o, p)| *o >= 0.0 && p.is_finite())
+            .filter(|(o, p)| *o < *p && (*o..=*p).contains(&estimate));
+        // Bounds that don't make a distribution fall back to the log-normal estimate
+        match (task.distribution, bounds) {
+            (Distribution::Pert, Some((o, p))) => {
+                if let Ok(pert) = Pert::new(o, p, estimate) {
+                    return TaskDuration::Pert((o + 4.0 * estimate + p) / 6.0, pert);
+                }
+            }
+            (Distribution::Triangular, Some((o, p))) => {
+                if let Ok(triangular) = Triangular::new(o, p, estimate) {
+                    return TaskDuration::Triangular((o + estimate + p) / 3.0, triangular);
+                }
+            }
+            _ => {}
+        }
+
+        // Based in this guy's musings:
+        // https://erikbern.com/2019/04/15/why-software-projects-take-longer-than-you-think-a-statistical-model.html
+        let sigma = task.sigma.filter(|s| s.is_finite() && *s >= 0.0).unwrap_or(
+            match task.risk.unwrap_or_default() {
+                Risk::Low => 0.25,
+                Risk::Medium => 0.5,
+                Risk::High => 1.0,
+            },
+        );
+        match LogNormal::from_mean_cv(1.0, sigma) {
+            Ok(log_normal) if sigma > 0.0 => TaskDuration::LogNormal(estimate, log_normal),
+            _ => TaskDuration::Fixed(estimate),
+        }
+    }
+
+    fn sample(&self, rng: &mut StdRng) -> f64 {
+        match self {
+            TaskDuration::Fixed(d) => *d,
+            TaskDuration::LogNormal(estimate, log_normal) => estimate * log_normal.sample(rng),
+            TaskDuration::Pert(_, pert) => pert.sample(rng),
+            TaskDuration::Triangular(_, triangular) => triangular.sample(rng),
+        }
+    }
+
+    /// How long the task takes on average
+    fn mean(&self) -> f64 {
+        match self {
+            TaskDuration::Fixed(d) | TaskDuration::LogNormal(d, _) => *d,
+            TaskDuration::Pert(mean, _) | TaskDuration::Triangular(mean, _) => *mean,
+        }
+    }
 }
 
 type Interval = Range<f64>;
@@ -99,26 +833,125 @@ type Schedule = Vec<Interval>;
 
 #[derive(Debug, Clone)]
 struct PeopleAllocation {
+    /// Fraction of full time, more than 0
+    capacities: Vec<f64>,
+    /// Time off and time before someone joins, ordered by start
+    blocked: Vec<Schedule>,
     schedules: Vec<Schedule>,
     booked_until: Vec<OrderedFloat<f64>>,
 }
 
 impl PeopleAllocation {
-    pub fn new(n: u32) -> Self {
+    /// Start dates and vacations are ignored when the calendar has no start date
+    pub fn new(people: &[Person], calendar: &Calendar) -> Self {
+        let blocked = people
+            .iter()
+            .map(|p| {
+                let before_start = p.start.and_then(|d| Some(0.0..calendar.offset(d)?));
+                let vacations = p.vacations.iter().filter_map(|v| {
+                    Some(calendar.offset(v.start)?..calendar.offset(v.end.succ_opt()?)?)
+                });
+                before_start
+                    .into_iter()
+                    .chain(vacations)
+                    .filter(|b| !b.is_empty())
+                    .sorted_by_key(|b| OrderedFloat(b.start))
+                    .collect_vec()
+            })
+            .collect_vec();
+
         PeopleAllocation {
-            schedules: (0..n).map(|_| vec![]).collect(),
-            booked_until: (0..n).map(|_| 0.0.into()).collect(),
+            capacities: people.iter().map(|p| p.capacity).collect(),
+            blocked,
+            schedules: people.iter().map(|_| vec![]).collect(),
+            booked_until: people.iter().map(|_| 0.0.into()).collect(),
         }
     }
 
-    /// Return the availabilities for all people starting at a given t, earliest available first
-    pub fn availabilities(&self, start: f64) -> impl Iterator<Item = (usize, f64)> + '_ {
-        self.booked_until
-            .iter()
-            .map(move |b| OrderedFloat::from(start.max(**b)))
-            .enumerate()
-            .sorted_by_key(|(_, v)| *v)
-            .map(|(i, v)| (i, v.0))
+    /// When each of `who`, or everyone, would do `effort` days of full-time work from a given t,
+    /// earliest finish first
+    pub fn availabilities<'a>(
+        &'a self,
+        who: Option<&'a [usize]>,
+        start: f64,
+        effort: f64,
+    ) -> impl Iterator<Item = (usize, Interval)> + 'a {
+        let everyone = 0..self.capacities.len();
+        who.map_or_else(|| everyone.collect_vec(), |who| who.to_vec())
+            .into_iter()
+            .map(move |i| (i, self.work(i, start.max(*self.booked_until[i]), effort)))
+            .sorted_by_key(|(i, v)| (OrderedFloat(v.end), *i))
+    }
+
+    /// Who does `effort` days of full-time work from a given t and when, the one who finishes
+    /// first, or up to `max_people` of `who` when they finish sooner together
+    ///
+    /// Every extra person adds `loss` of the effort, the rest is split by capacity.
+    pub fn team(
+        &self,
+        who: Option<&[usize]>,
+        start: f64,
+        effort: f64,
+        max_people: usize,
+        loss: f64,
+    ) -> Vec<(usize, Interval)> {
+        let mut best = vec![self.availabilities(who, start, effort).next().unwrap()];
+        if max_people < 2 {
+            return best;
+        }
+
+        // Whoever is free first joins first, the fastest of those first
+        let everyone = 0..self.capacities.len();
+        let by_free = who
+            .map_or_else(|| everyone.collect_vec(), |who| who.to_vec())
+            .into_iter()
+            .map(|i| (i, self.work(i, start.max(*self.booked_until[i]), 0.0).start))
+            .sorted_by_key(|(i, free)| {
+                let capacity = Reverse(OrderedFloat(self.capacities[*i]));
+                (OrderedFloat(*free), capacity, *i)
+            })
+            .collect_vec();
+        for size in 2..=max_people.min(by_free.len()) {
+            let members = &by_free[..size];
+            let together = members.iter().map(|(_, free)| *free).fold(start, f64::max);
+            let total = effort * (1.0 + loss * (size - 1) as f64);
+            let capacity: f64 = members.iter().map(|(i, _)| self.capacities[*i]).sum();
+            let team = members
+                .iter()
+                .map(|(i, _)| {
+                    let share = total * self.capacities[*i] / capacity;
+                    (*i, self.work(*i, together, share))
+                })
+                .collect_vec();
+            if span(&team).end < span(&best).end - 1e-9 {
+                best = team;
+            }
+        }
+        best
+    }
+
+    /// Work on something from t on at the person's pace, pausing while they're away
+    fn work(&self, i: usize, mut t: f64, effort: f64) -> Interval {
+        let blocked = &self.blocked[i];
+        // Don't start on a day off
+        for b in blocked {
+            if b.start <= t && t < b.end {
+                t = b.end;
+            }
+        }
+        let start = t;
+        let mut remaining = effort / self.capacities[i];
+        for b in blocked {
+            if b.end <= t {
+                continue;
+            }
+            if t + remaining <= b.start {
+                break;
+            }
+            remaining -= (b.start - t).max(0.0);
+            t = b.end;
+        }
+        start..t + remaining
     }
 
     pub fn book(&mut self, i: usize, interval: Interval) {
@@ -130,6 +963,19 @@ impl PeopleAllocation {
     }
 }
 
+/// From the first to start to the last to finish
+fn span(team: &[(usize, Interval)]) -> Interval {
+    let start = team
+        .iter()
+        .map(|(_, v)| v.start)
+        .fold(f64::INFINITY, f64::min);
+    let end = team
+        .iter()
+        .map(|(_, v)| v.end)
+        .fold(f64::NEG_INFINITY, f64::max);
+    start..end
+}
+
 fn overlaps<A: PartialOrd>(a: &Range<A>, b: &Range<A>) -> bool {
     a.start < b.end && b.start < a.end
 }
@@ -148,3 +994,121 @@ pub fn convert_rng(rng: &Range<f64>) -> Range<u32> {
 }
 
 // P(working) = P(started) * (1 - P(stopped))
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datamodel::Deadline;
+
+    fn quantiles(results: &SimulationResults) -> Vec<(String, [Option<f64>; 6])> {
+        results
+            .task_stats
+            .iter()
+            .map(|(uid, s)| {
+                let q = |q: &Quantogram, p| q.quantile(p);
+                let quantiles = [
+                    q(&s.start, 0.1),
+                    q(&s.start, 0.5),
+                    q(&s.start, 0.9),
+                    q(&s.end, 0.1),
+                    q(&s.end, 0.5),
+                    q(&s.end, 0.9),
+                ];
+                (uid.clone(), quantiles)
+            })
+            .sorted_by(|a, b| a.0.cmp(&b.0))
+            .collect()
+    }
+
+    #[test]
+    fn bounds_without_a_distribution_fall_back_to_log_normal() {
+        for (optimistic, pessimistic) in [(1.0, f64::INFINITY), (-1.0, 3.0), (f64::NAN, 3.0)] {
+            for distribution in [Distribution::Pert, Distribution::Triangular] {
+                let task = Task {
+                    estimate: Some(2.0),
+                    distribution,
+                    optimistic: Some(optimistic),
+                    pessimistic: Some(pessimistic),
+                    ..Default::default()
+                };
+                assert!(matches!(
+                    TaskDuration::new(&task),
+                    TaskDuration::LogNormal(2.0, _)
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn same_seed_same_forecast() {
+        let project = Project {
+            uid: "p".to_owned(),
+            people: Person::team(2),
+            seed: 42,
+            ..Default::default()
+        };
+        let task = |uid: &str, estimate, risk, dependencies: &[&str]| Task {
+            project_uid: "p".to_owned(),
+            uid: uid.to_owned(),
+            id: uid.to_owned(),
+            estimate: Some(estimate),
+            risk: Some(risk),
+            dependencies: dependencies
+                .iter()
+                .map(|d| (d.to_string(), Dependency::default()))
+                .collect(),
+            ..Default::default()
+        };
+        let tasks = vec![
+            task("a", 3.0, Risk::Low, &[]),
+            task("b", 5.0, Risk::High, &["a"]),
+            task("c", 2.0, Risk::Medium, &[]),
+            task("d", 4.0, Risk::Medium, &["b", "c"]),
+        ];
+        let today = NaiveDate::from_ymd_opt(2026, 1, 5).unwrap();
+
+        let first = simulate_tasks(tasks.clone().into_iter(), &project, today);
+        let second = simulate_tasks(tasks.into_iter(), &project, today);
+        assert_eq!(first.iterations, second.iterations);
+        assert_eq!(quantiles(&first).len(), 4);
+        assert_eq!(quantiles(&first), quantiles(&second));
+        assert_eq!(first.criticality, second.criticality);
+    }
+
+    #[test]
+    fn deadline_chance_counts_the_iterations_that_made_it() {
+        let project = Project {
+            uid: "p".to_owned(),
+            people: Person::team(1),
+            iterations: 500,
+            ..Default::default()
+        };
+        let work = Task {
+            project_uid: "p".to_owned(),
+            uid: "a".to_owned(),
+            estimate: Some(3.0),
+            risk: Some(Risk::High),
+            ..Default::default()
+        };
+        let milestone = |deadline| Task {
+            project_uid: "p".to_owned(),
+            uid: "m".to_owned(),
+            r#type: TaskType::Milestone,
+            dependencies: [("a".to_owned(), Dependency::default())].into(),
+            deadline,
+            ..Default::default()
+        };
+        let today = NaiveDate::from_ymd_opt(2026, 1, 5).unwrap();
+        let chance = |deadline| {
+            let tasks = [work.clone(), milestone(Some(Deadline::Day(deadline)))];
+            simulate_tasks(tasks.into_iter(), &project, today).deadline_chance["m"]
+        };
+
+        let rs = simulate_tasks([work.clone(), milestone(None)].into_iter(), &project, today);
+        assert!(rs.deadline_chance.is_empty());
+        let end = &rs.task_stats["m"].end;
+        let at_min = chance(end.min().unwrap());
+        assert!(at_min > 0.0 && at_min < 0.5, "{}", at_min);
+        assert_eq!(chance(end.max().unwrap()), 1.0);
+    }
+}
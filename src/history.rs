@@ -0,0 +1,374 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    datamodel::{Project, Task},
+    hstable::{Hashable, SortColl, Sortable},
+};
+
+/// A change to a single task or project, with its state before and after, or several at once
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+#[allow(clippy::large_enum_variant)]
+pub enum Change {
+    /// `before` is empty for a new task, `after` is empty for a deleted task
+    Task {
+        before: Option<Task>,
+        after: Option<Task>,
+    },
+    Project {
+        before: Project,
+        after: Project,
+    },
+    /// Changes that are undone and redone together, in order
+    Batch {
+        changes: Vec<Change>,
+    },
+}
+
+impl Change {
+    pub fn project_uid(&self) -> &str {
+        match self {
+            Change::Task { before, after } => after
+                .as_ref()
+                .or(before.as_ref())
+                .map(|t| t.project_uid.as_str())
+                .unwrap_or_default(),
+            Change::Project { after, .. } => &after.uid,
+            Change::Batch { changes } => {
+                changes.first().map(|c| c.project_uid()).unwrap_or_default()
+            }
+        }
+    }
+
+    pub fn is_noop(&self) -> bool {
+        match self {
+            Change::Task { before, after } => before == after,
+            Change::Project { before, after } => before == after,
+            Change::Batch { changes } => changes.iter().all(|c| c.is_noop()),
+        }
+    }
+
+    /// Changes to one project as a single change, leaving out the ones that change nothing
+    pub fn batch(changes: Vec<Change>) -> Change {
+        let mut changes: Vec<Change> = changes.into_iter().filter(|c| !c.is_noop()).collect();
+        match changes.len() {
+            1 => changes.remove(0),
+            _ => Change::Batch { changes },
+        }
+    }
+
+    /// The change that undoes this change
+    pub fn inverse(&self) -> Change {
+        match self.clone() {
+            Change::Task { before, after } => Change::Task {
+                before: after,
+                after: before,
+            },
+            Change::Project { before, after } => Change::Project {
+                before: after,
+                after: before,
+            },
+            Change::Batch { changes } => Change::Batch {
+                changes: changes.iter().rev().map(|c| c.inverse()).collect(),
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+#[allow(clippy::large_enum_variant)]
+pub enum Event {
+    Change { change: Change },
+    Undo,
+    Redo,
+}
+
+/// An entry in the append-only change log of a project
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LogEntry {
+    pub project_uid: String,
+    pub seq: u64,
+    pub event: Event,
+}
+
+impl Hashable for LogEntry {
+    type Coll = SortColl<LogEntry>;
+    type HashKey = String;
+
+    fn hash_key(&self) -> &String {
+        &self.project_uid
+    }
+}
+
+impl Sortable for LogEntry {
+    type SortKey = u64;
+
+    fn sort_key(&self) -> &u64 {
+        &self.seq
+    }
+}
+
+/// How many changes of a project can be undone, and redone, once its log is compacted
+pub const MAX_UNDO: usize = 100;
+
+/// Storage that changes can be applied to and recorded in, inside a single transaction
+pub trait ChangeLog {
+    /// Make the `after` state of the change the current state
+    fn apply(&mut self, change: &Change) -> Result<()>;
+    fn log_entries(&self, project_uid: &str) -> Result<Vec<LogEntry>>;
+    /// Append an event to the end of the log of a project, returns its `seq`
+    fn append(&mut self, project_uid: &str, event: Event) -> Result<u64>;
+    /// Replace the whole log of a project
+    fn replace_log(&mut self, project_uid: &str, entries: Vec<LogEntry>) -> Result<()>;
+}
+
+/// Append an event to the log of a project, and compact the log once it gets long
+fn append(log: &mut impl ChangeLog, project_uid: &str, event: Event) -> Result<()> {
+    // A compacted log starts again at 0, so `seq` is how many entries came before
+    let seq = log.append(project_uid, event)?;
+    if seq >= 4 * MAX_UNDO as u64 {
+        let stacks = UndoStacks::replay(&log.log_entries(project_uid)?);
+        log.replace_log(project_uid, stacks.compact(project_uid))?;
+    }
+    Ok(())
+}
+
+/// Apply a change and record it in the log of its project
+pub fn record(log: &mut impl ChangeLog, change: Change) -> Result<()> {
+    if change.is_noop() {
+        return Ok(());
+    }
+    log.apply(&change)?;
+    let project_uid = change.project_uid().to_owned();
+    append(log, &project_uid, Event::Change { change })
+}
+
+/// Undo the most recent change in a project, returns the change if there was one
+pub fn undo(log: &mut impl ChangeLog, project_uid: &str) -> Result<Option<Change>> {
+    let mut stacks = UndoStacks::replay(&log.log_entries(project_uid)?);
+    let Some(change) = stacks.undo.pop() else {
+        return Ok(None);
+    };
+    log.apply(&change.inverse())?;
+    append(log, project_uid, Event::Undo)?;
+    Ok(Some(change))
+}
+
+/// Redo the most recently undone change in a project, returns the change if there was one
+pub fn redo(log: &mut impl ChangeLog, project_uid: &str) -> Result<Option<Change>> {
+    let mut stacks = UndoStacks::replay(&log.log_entries(project_uid)?);
+    let Some(change) = stacks.redo.pop() else {
+        return Ok(None);
+    };
+    log.apply(&change)?;
+    append(log, project_uid, Event::Redo)?;
+    Ok(Some(change))
+}
+
+/// The changes that can currently be undone and redone, most recent last
+#[derive(Debug, Default)]
+pub struct UndoStacks {
+    pub undo: Vec<Change>,
+    pub redo: Vec<Change>,
+}
+
+impl UndoStacks {
+    /// Rebuild the undo and redo stacks by replaying the log from the start
+    pub fn replay(entries: &[LogEntry]) -> Self {
+        let mut ret = Self::default();
+        for entry in entries {
+            match &entry.event {
+                Event::Change { change } => {
+                    ret.undo.push(change.clone());
+                    ret.redo.clear();
+                }
+                Event::Undo => {
+                    if let Some(change) = ret.undo.pop() {
+                        ret.redo.push(change);
+                    }
+                }
+                Event::Redo => {
+                    if let Some(change) = ret.redo.pop() {
+                        ret.undo.push(change);
+                    }
+                }
+            }
+        }
+        ret
+    }
+
+    /// The shortest log that replays to these stacks, forgetting all but `MAX_UNDO` changes of each
+    ///
+    /// The log is at most `3 * MAX_UNDO` entries long.
+    pub fn compact(&self, project_uid: &str) -> Vec<LogEntry> {
+        let undo = &self.undo[self.undo.len().saturating_sub(MAX_UNDO)..];
+        let redo = &self.redo[self.redo.len().saturating_sub(MAX_UNDO)..];
+        // Undoing moves the last change onto the redo stack, so the changes to redo go in reversed
+        let changes = undo
+            .iter()
+            .chain(redo.iter().rev())
+            .map(|change| Event::Change {
+                change: change.clone(),
+            });
+        let undos = std::iter::repeat_n(Event::Undo, redo.len());
+        changes
+            .chain(undos)
+            .enumerate()
+            .map(|(seq, event)| LogEntry {
+                project_uid: project_uid.to_owned(),
+                seq: seq as u64,
+                event,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct TestLog {
+        project: Project,
+        entries: Vec<LogEntry>,
+    }
+
+    impl ChangeLog for TestLog {
+        fn apply(&mut self, change: &Change) -> Result<()> {
+            match change {
+                Change::Project { after, .. } => self.project = after.clone(),
+                Change::Batch { changes } => {
+                    for change in changes {
+                        self.apply(change)?;
+                    }
+                }
+                Change::Task { .. } => {}
+            }
+            Ok(())
+        }
+
+        fn log_entries(&self, _project_uid: &str) -> Result<Vec<LogEntry>> {
+            Ok(self.entries.clone())
+        }
+
+        fn append(&mut self, project_uid: &str, event: Event) -> Result<u64> {
+            let seq = self.entries.last().map(|e| e.seq + 1).unwrap_or_default();
+            self.entries.push(LogEntry {
+                project_uid: project_uid.to_owned(),
+                seq,
+                event,
+            });
+            Ok(seq)
+        }
+
+        fn replace_log(&mut self, _project_uid: &str, entries: Vec<LogEntry>) -> Result<()> {
+            self.entries = entries;
+            Ok(())
+        }
+    }
+
+    fn rename(log: &mut TestLog, name: &str) {
+        let before = log.project.clone();
+        let after = Project {
+            name: name.to_owned(),
+            ..before.clone()
+        };
+        record(log, Change::Project { before, after }).unwrap();
+    }
+
+    fn names(changes: &[Change]) -> Vec<&str> {
+        changes
+            .iter()
+            .map(|change| match change {
+                Change::Project { after, .. } => after.name.as_str(),
+                _ => "",
+            })
+            .collect()
+    }
+
+    #[test]
+    fn undo_and_redo() {
+        let mut log = TestLog::default();
+        for name in ["a", "b", "c"] {
+            rename(&mut log, name);
+        }
+        assert!(undo(&mut log, "").unwrap().is_some());
+        assert!(undo(&mut log, "").unwrap().is_some());
+        assert_eq!(log.project.name, "a");
+        assert!(redo(&mut log, "").unwrap().is_some());
+        assert_eq!(log.project.name, "b");
+
+        // A new change can't be redone past
+        rename(&mut log, "d");
+        assert!(redo(&mut log, "").unwrap().is_none());
+        let stacks = UndoStacks::replay(&log.entries);
+        assert_eq!(names(&stacks.undo), ["a", "b", "d"]);
+        assert!(stacks.redo.is_empty());
+    }
+
+    #[test]
+    fn batches_are_undone_together() {
+        let mut log = TestLog::default();
+        rename(&mut log, "a");
+        let renamed = |name: &str, before: &Project| Change::Project {
+            before: before.clone(),
+            after: Project {
+                name: name.to_owned(),
+                ..before.clone()
+            },
+        };
+        let b = renamed("b", &log.project);
+        let c = match &b {
+            Change::Project { after, .. } => renamed("c", after),
+            _ => unreachable!(),
+        };
+        let batch = Change::batch(vec![b.clone(), c]);
+        assert!(matches!(&batch, Change::Batch { changes } if changes.len() == 2));
+        record(&mut log, batch).unwrap();
+        assert_eq!(log.project.name, "c");
+
+        assert!(undo(&mut log, "").unwrap().is_some());
+        assert_eq!(log.project.name, "a");
+        assert!(redo(&mut log, "").unwrap().is_some());
+        assert_eq!(log.project.name, "c");
+
+        // A batch of one is just that change
+        assert!(matches!(Change::batch(vec![b]), Change::Project { .. }));
+    }
+
+    #[test]
+    fn compacted_log_replays_to_the_same_stacks() {
+        let mut log = TestLog::default();
+        for i in 0..150 {
+            rename(&mut log, &i.to_string());
+        }
+        for _ in 0..20 {
+            undo(&mut log, "").unwrap();
+        }
+        let stacks = UndoStacks::replay(&log.entries);
+        let compacted = UndoStacks::replay(&stacks.compact(""));
+        assert_eq!(names(&compacted.undo), names(&stacks.undo[30..]));
+        assert_eq!(names(&compacted.redo), names(&stacks.redo));
+    }
+
+    #[test]
+    fn long_logs_are_compacted() {
+        let mut log = TestLog::default();
+        for i in 0..1000 {
+            rename(&mut log, &i.to_string());
+        }
+        assert!(log.entries.len() <= 4 * MAX_UNDO);
+        assert!(undo(&mut log, "").unwrap().is_some());
+        assert_eq!(log.project.name, "998");
+
+        let mut undone = 1;
+        while undo(&mut log, "").unwrap().is_some() {
+            undone += 1;
+        }
+        assert!(undone >= MAX_UNDO);
+        assert!(redo(&mut log, "").unwrap().is_some());
+        assert!(log.entries.len() <= 4 * MAX_UNDO);
+    }
+}
@@ -17,7 +17,7 @@ pub trait Sortable {
     fn sort_key(&self) -> &Self::SortKey;
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct HSTable<T>
 where
     T: Hashable,
@@ -26,6 +26,17 @@ where
     elements: HashMap<T::HashKey, Vec<T>>,
 }
 
+impl<T: Hashable> FromIterator<T> for HSTable<T>
+where
+    T::HashKey: std::hash::Hash + Eq + Clone,
+{
+    fn from_iter<I: IntoIterator<Item = T>>(xs: I) -> Self {
+        let mut ret = Self::new();
+        xs.into_iter().for_each(|x| ret.insert(x));
+        ret
+    }
+}
+
 impl<T: Hashable> HSTable<T>
 where
     T: Hashable,
@@ -37,12 +48,6 @@ where
         }
     }
 
-    pub fn from_iter(xs: impl IntoIterator<Item = T>) -> Self {
-        let mut ret = Self::new();
-        xs.into_iter().for_each(|x| ret.insert(x));
-        ret
-    }
-
     pub fn insert(&mut self, x: T) {
         let pk = x.hash_key();
         let Some(existing) = self.elements.get_mut(pk) else {
@@ -63,38 +68,32 @@ where
     T: Hashable,
     T::HashKey: std::hash::Hash + Eq + Clone,
 {
-    pub fn get1<Q: ?Sized>(&self, pk: &Q) -> Option<&T>
+    pub fn get1<Q>(&self, pk: &Q) -> Option<&T>
     where
         T::HashKey: Borrow<Q>,
-        Q: std::hash::Hash + Eq,
+        Q: ?Sized + std::hash::Hash + Eq,
     {
         self.elements
             .get(pk)
-            .map(|existing| {
-                assert!(existing.len() <= 1);
-                existing
-            })
-            .and_then(|existing| existing.get(0))
+            .inspect(|existing| assert!(existing.len() <= 1))
+            .and_then(|existing| existing.first())
     }
 
-    pub fn get1_mut<Q: ?Sized>(&mut self, pk: &Q) -> Option<&mut T>
+    pub fn get1_mut<Q>(&mut self, pk: &Q) -> Option<&mut T>
     where
         T::HashKey: Borrow<Q>,
-        Q: std::hash::Hash + Eq,
+        Q: ?Sized + std::hash::Hash + Eq,
     {
         self.elements
             .get_mut(pk)
-            .map(|existing| {
-                assert!(existing.len() <= 1);
-                existing
-            })
+            .inspect(|existing| assert!(existing.len() <= 1))
             .and_then(|existing| existing.get_mut(0))
     }
 
-    pub fn remove1<Q: ?Sized>(&mut self, pk: &Q) -> Option<T>
+    pub fn remove1<Q>(&mut self, pk: &Q) -> Option<T>
     where
         T::HashKey: Borrow<Q>,
-        Q: std::hash::Hash + Eq,
+        Q: ?Sized + std::hash::Hash + Eq,
     {
         self.elements.get_mut(pk).and_then(|existing| {
             if !existing.is_empty() {
@@ -111,12 +110,12 @@ where
     T: Hashable + Sortable,
     T::HashKey: std::hash::Hash + Eq + Clone,
 {
-    pub fn get2<Q: ?Sized, R: ?Sized>(&self, pk: &Q, sk: &R) -> Option<&T>
+    pub fn get2<Q, R>(&self, pk: &Q, sk: &R) -> Option<&T>
     where
         T::HashKey: Borrow<Q>,
-        Q: std::hash::Hash + Eq,
+        Q: ?Sized + std::hash::Hash + Eq,
         T::SortKey: Borrow<R>,
-        R: Ord,
+        R: ?Sized + Ord,
     {
         self.elements.get(pk).and_then(|existing| {
             match existing.binary_search_by(|a| a.sort_key().borrow().cmp(sk)) {
@@ -126,12 +125,12 @@ where
         })
     }
 
-    pub fn get2_mut<Q: ?Sized, R: ?Sized>(&mut self, pk: &Q, sk: &R) -> Option<&mut T>
+    pub fn get2_mut<Q, R>(&mut self, pk: &Q, sk: &R) -> Option<&mut T>
     where
         T::HashKey: Borrow<Q>,
-        Q: std::hash::Hash + Eq,
+        Q: ?Sized + std::hash::Hash + Eq,
         T::SortKey: Borrow<R>,
-        R: Ord,
+        R: ?Sized + Ord,
     {
         self.elements.get_mut(pk).and_then(|existing| {
             match existing.binary_search_by(|a| a.sort_key().borrow().cmp(sk)) {
@@ -141,12 +140,12 @@ where
         })
     }
 
-    pub fn remove2<Q: ?Sized, R: ?Sized>(&mut self, pk: &Q, sk: &R) -> Option<T>
+    pub fn remove2<Q, R>(&mut self, pk: &Q, sk: &R) -> Option<T>
     where
         T::HashKey: Borrow<Q>,
-        Q: std::hash::Hash + Eq,
+        Q: ?Sized + std::hash::Hash + Eq,
         T::SortKey: Borrow<R>,
-        R: Ord,
+        R: ?Sized + Ord,
     {
         self.elements.get_mut(pk).and_then(|existing| {
             match existing.binary_search_by(|a| a.sort_key().borrow().cmp(sk)) {
@@ -156,18 +155,26 @@ where
         })
     }
 
-    pub fn get_many<Q: ?Sized>(&self, pk: &Q) -> impl Iterator<Item = &T> + '_
+    pub fn get_many<Q>(&self, pk: &Q) -> impl Iterator<Item = &T> + '_
     where
         T::HashKey: Borrow<Q>,
-        Q: std::hash::Hash + Eq,
+        Q: ?Sized + std::hash::Hash + Eq,
     {
         self.elements.get(pk).into_iter().flat_map(|xs| xs.iter())
     }
 
-    pub fn into_many<Q: ?Sized>(mut self, pk: &Q) -> impl Iterator<Item = T>
+    pub fn remove_many<Q>(&mut self, pk: &Q) -> Vec<T>
+    where
+        T::HashKey: Borrow<Q>,
+        Q: ?Sized + std::hash::Hash + Eq,
+    {
+        self.elements.remove(pk).unwrap_or_default()
+    }
+
+    pub fn into_many<Q>(mut self, pk: &Q) -> impl Iterator<Item = T>
     where
         T::HashKey: Borrow<Q>,
-        Q: std::hash::Hash + Eq,
+        Q: ?Sized + std::hash::Hash + Eq,
     {
         self.elements
             .remove_entry(pk)
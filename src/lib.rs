@@ -0,0 +1,17 @@
+//! Planning and forecasting, shared by the web server and the command line tool
+#[macro_use]
+extern crate rocket;
+
+pub mod calendar;
+pub mod compare;
+pub mod datamodel;
+pub mod db;
+pub mod forecast;
+pub mod history;
+pub mod hstable;
+pub mod ids;
+pub mod pool;
+pub mod portfolio;
+pub mod report;
+pub mod spreadsheet;
+pub mod topo_queue;
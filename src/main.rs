@@ -2,48 +2,69 @@
 extern crate rocket;
 
 use std::{
-    cmp::{max, min},
-    collections::HashMap,
+    cmp::{max, min, Reverse},
+    collections::{BTreeSet, HashMap, HashSet},
     ops::Range,
 };
 
-use datamodel::{roughly_sort_tasks, Task, TaskUpdate};
-use db::Database;
-use forecast::simulate_tasks;
-use ids::unique_id;
+use chrono::{Datelike, NaiveDate, Weekday};
 use itertools::Itertools;
 use ordered_float::OrderedFloat;
+use planny::{
+    calendar::{today, Calendar},
+    compare::{compare_plans, Plan},
+    datamodel::{
+        ancestors, children, is_part_of, parse_dependency, parse_skills, roughly_sort_tasks,
+        Deadline, Dependency, Person, PoolMember, Task, TaskStatus, TaskUpdate, Vacation,
+    },
+    db::Database,
+    forecast::{convert_rng, query_minmax, MAX_ITERATIONS},
+    history::Change,
+    ids::unique_id,
+    pool::{allocations, overallocation_warnings, share_out},
+    portfolio::{self, find_task, project_plan, simulate_project, ProjectPlan},
+    report::{deadline_warnings, estimate_warnings, staffing_warnings, Cycles},
+    spreadsheet::{export_tasks, import_tasks, ImportReport},
+};
 use rocket::{
     form::Form,
     fs::{relative, FileServer},
+    http::Header,
     response::Redirect,
+    tokio::io::AsyncReadExt,
     State,
 };
 use rocket_dyn_templates::{context, Template};
 use serde::Serialize;
-use viewmodel::{Choice, ProjectNameForm, ProjectPeopleForm, TaskDependencyView, TaskView};
+use viewmodel::{
+    CalendarForm, Choice, CsvImportForm, DependencyForm, PersonForm, PersonView, PoolMemberForm,
+    ProjectConfidenceForm, ProjectEfficiencyLossForm, ProjectIterationsForm, ProjectNameForm,
+    ProjectSchedulingForm, ProjectSeedForm, RulerTick, SnapshotForm, TaskDependencyView, TaskView,
+};
 
 use crate::{
-    forecast::{convert_rng, query_minmax},
-    render_forecast::render_dist,
+    render_forecast::{render_dist, render_tornado, TORNADO_ROW},
     svg::RenderedSvg,
     viewmodel::TaskForm,
 };
 
-mod datamodel;
-mod db;
-mod forecast;
-mod hstable;
-mod ids;
+mod api;
 mod render_forecast;
 mod svg;
-mod topo_queue;
 mod viewmodel;
 
 // Not sure why I need a wrapper type, but if I use Database directly
 // Rocket thinks I didn't add it
 struct Db(Database);
 
+#[derive(Responder)]
+#[response(content_type = "text/csv")]
+struct CsvDownload(String, Header<'static>);
+
+/// A partial for the project tab, with a header that selects its tab
+#[derive(Responder)]
+struct TabPartial(Option<Template>, Header<'static>);
+
 // A variant of anyhow::Result that has responable errors
 type AnyResult<T> = Result<T, rocket::response::Debug<anyhow::Error>>;
 
@@ -61,10 +82,61 @@ fn index(db: &State<Db>) -> AnyResult<Template> {
 #[get("/project/<project_uid>")]
 fn get_project(project_uid: &str, db: &State<Db>) -> AnyResult<Template> {
     let project = db.0.project(project_uid)?;
+    let undo = db.0.undo_stacks(project_uid)?;
     Ok(Template::render(
         "project",
         context! {
             project,
+            can_undo: !undo.undo.is_empty(),
+            can_redo: !undo.redo.is_empty(),
+        },
+    ))
+}
+
+/// Several projects forecast together, with the projects they wait for
+#[get("/portfolio?<projects>&<shared>")]
+fn get_portfolio(projects: Vec<&str>, shared: bool, db: &State<Db>) -> AnyResult<Template> {
+    let all_projects = db.0.projects()?;
+    let allocations = allocations(&db.0.pool()?, all_projects.iter_all());
+    let mut plans = portfolio::plans(&projects, &all_projects, &db.0.tasks()?);
+    for (p, _) in &mut plans {
+        share_out(p, &allocations);
+    }
+    #[derive(Serialize)]
+    struct ProjectChoice<'a> {
+        uid: &'a str,
+        name: &'a str,
+        selected: bool,
+        /// Comes along because a chosen project waits for it
+        upstream: bool,
+    }
+    let choices = all_projects
+        .iter_all()
+        .map(|p| ProjectChoice {
+            uid: &p.uid,
+            name: &p.name,
+            selected: projects.contains(&p.uid.as_str()),
+            upstream: !projects.contains(&p.uid.as_str())
+                && plans.iter().any(|(plan, _)| plan.uid == p.uid),
+        })
+        .collect_vec();
+
+    let uids = plans.iter().map(|(p, _)| p.uid.as_str()).collect_vec();
+    let warnings = overallocation_warnings(&allocations, &uids);
+    let (forecast, cycles) = match portfolio::forecast(plans, shared, today()) {
+        Ok(mut forecast) => {
+            forecast.warnings.splice(0..0, warnings);
+            (Some(forecast), vec![])
+        }
+        Err(Cycles(cycles)) => (None, cycles),
+    };
+    Ok(Template::render(
+        "portfolio",
+        context! {
+            projects: choices,
+            shared,
+            forecast,
+            cycles,
         },
     ))
 }
@@ -91,12 +163,32 @@ fn post_project_name(
 
 #[get("/project/<project_uid>/tasks")]
 fn get_tasks(project_uid: &str, db: &State<Db>) -> AnyResult<Template> {
+    task_grid(project_uid, db, None)
+}
+
+/// Render the task grid, with the outcome of a CSV import if there was one
+fn task_grid(
+    project_uid: &str,
+    db: &State<Db>,
+    import: Option<ImportReport>,
+) -> AnyResult<Template> {
     let project = db.0.project(project_uid)?;
+    let projects = db.0.projects()?;
+    let all_tasks = db.0.tasks()?;
 
     let task_map: HashMap<String, Task> = HashMap::from_iter(
-        db.0.tasks()?
-            .into_many(project_uid)
-            .map(|t| (t.uid.clone(), t)),
+        all_tasks
+            .get_many(project_uid)
+            .map(|t| (t.uid.clone(), t.clone())),
+    );
+    // Tasks of other projects show up as `Project/ID`
+    let other_tasks: HashMap<&str, (String, &Task)> = HashMap::from_iter(
+        projects
+            .iter_all()
+            .filter(|p| p.uid != project_uid)
+            .flat_map(|p| all_tasks.get_many(&p.uid).map(move |t| (p, t)))
+            .filter(|(_, t)| !t.id.is_empty())
+            .map(|(p, t)| (t.uid.as_str(), (format!("{}/{}", p.name, t.id), t))),
     );
 
     let sorted_tasks = roughly_sort_tasks(task_map.values());
@@ -111,25 +203,111 @@ fn get_tasks(project_uid: &str, db: &State<Db>) -> AnyResult<Template> {
         })
         .sorted_by_key(|t| t.value.clone())
         .collect_vec();
+    let other_task_list = other_tasks
+        .values()
+        .map(|(id, t)| Choice {
+            value: id.clone(),
+            caption: t.title.clone(),
+        })
+        .sorted_by_key(|t| t.value.clone())
+        .collect_vec();
 
-    let tasks = sorted_tasks
+    // Epics go right above their subtasks, keeping the order of the sorted tasks
+    let by_uid: HashMap<&str, &Task> = HashMap::from_iter(
+        sorted_tasks
+            .sorted_tasks
+            .iter()
+            .map(|t| (t.uid.as_str(), t)),
+    );
+    let position: HashMap<&str, usize> = HashMap::from_iter(
+        sorted_tasks
+            .sorted_tasks
+            .iter()
+            .enumerate()
+            .map(|(i, t)| (t.uid.as_str(), i)),
+    );
+    let subtasks = children(&by_uid);
+    let mut tree: Vec<(usize, usize)> = vec![];
+    let mut visited: HashSet<usize> = HashSet::new();
+    // Tasks that are part of a loop of epics have no root, they go last
+    let roots = sorted_tasks
         .sorted_tasks
+        .iter()
+        .enumerate()
+        .filter(|(_, t)| ancestors(&by_uid, t).is_empty())
+        .chain(sorted_tasks.sorted_tasks.iter().enumerate())
+        .map(|(i, _)| i)
+        .collect_vec();
+    for root in roots {
+        let mut stack = vec![(root, 0)];
+        while let Some((i, depth)) = stack.pop() {
+            if !visited.insert(i) {
+                continue;
+            }
+            tree.push((i, depth));
+            let uid = sorted_tasks.sorted_tasks[i].uid.as_str();
+            for child in subtasks
+                .get(uid)
+                .into_iter()
+                .flatten()
+                .map(|c| position[c])
+                .sorted()
+                .rev()
+            {
+                stack.push((child, depth + 1));
+            }
+        }
+    }
+
+    let tasks = tree
         .into_iter()
-        .map(|t| TaskView {
-            uid: t.uid,
-            typ: t.r#type,
-            id: t.id,
-            title: t.title,
+        .map(|(i, depth)| (&sorted_tasks.sorted_tasks[i], depth))
+        .map(|(t, depth)| TaskView {
+            uid: t.uid.clone(),
+            typ: t.r#type.clone(),
+            id: t.id.clone(),
+            title: t.title.clone(),
             estimate: t.estimate,
+            distribution: t.distribution,
+            optimistic: t.optimistic,
+            pessimistic: t.pessimistic,
+            sigma: t.sigma,
             risk: t.risk,
+            skills: t.skills.iter().join(" "),
+            assignee: t.assignee.clone().unwrap_or_default(),
+            status: t.status,
+            started: t.started,
+            finished: t.finished,
+            remaining: t.remaining,
+            deadline: t.deadline.map(|d| d.to_string()).unwrap_or_default(),
+            parent: t
+                .parent
+                .as_deref()
+                .and_then(|p| by_uid.get(p))
+                .map(|p| p.id.clone())
+                .unwrap_or_default(),
+            depth,
+            subtasks: subtasks.get(t.uid.as_str()).map_or(0, |s| s.len()),
+            ancestors: ancestors(&by_uid, t)
+                .into_iter()
+                .map(|a| a.to_owned())
+                .collect(),
+            priority: t.priority,
+            max_people: t.max_people,
             dependencies: t
                 .dependencies
                 .iter()
-                .filter_map(|d| task_map.get(d).cloned())
-                .map(|t| TaskDependencyView {
-                    uid: t.uid,
-                    id: t.id,
-                    title: t.title,
+                .filter_map(|(d, dependency)| match dependency.project {
+                    None => task_map.get(d).map(|t| (t.id.clone(), t, dependency)),
+                    Some(_) => other_tasks
+                        .get(d.as_str())
+                        .map(|(id, t)| (id.clone(), *t, dependency)),
+                })
+                .map(|(id, t, dependency)| TaskDependencyView {
+                    uid: t.uid.clone(),
+                    id,
+                    title: t.title.clone(),
+                    link: dependency.to_string(),
                 })
                 .sorted_by(|a, b| human_sort::compare(&a.id, &b.id))
                 .collect(),
@@ -144,6 +322,8 @@ fn get_tasks(project_uid: &str, db: &State<Db>) -> AnyResult<Template> {
         ));
     }
 
+    let undo = db.0.undo_stacks(project_uid)?;
+
     Ok(Template::render(
         "partials/task-grid",
         context! {
@@ -152,34 +332,387 @@ fn get_tasks(project_uid: &str, db: &State<Db>) -> AnyResult<Template> {
             project,
             tasks,
             task_list,
+            other_task_list,
             warnings,
+            import,
+            can_undo: !undo.undo.is_empty(),
+            can_redo: !undo.redo.is_empty(),
         },
     ))
 }
 
 #[get("/project/<project_uid>/people")]
-fn get_people(project_uid: &str, db: &State<Db>) -> AnyResult<Template> {
-    let project = db.0.project(project_uid)?.unwrap();
-    Ok(Template::render(
+fn get_people(project_uid: &str, db: &State<Db>) -> AnyResult<Option<Template>> {
+    render_people(project_uid, vec![], db)
+}
+
+fn render_people(
+    project_uid: &str,
+    warnings: Vec<String>,
+    db: &State<Db>,
+) -> AnyResult<Option<Template>> {
+    let Some(project) = db.0.project(project_uid)? else {
+        return Ok(None);
+    };
+    let undo = db.0.undo_stacks(project_uid)?;
+    let pool = db.0.pool()?;
+    let mut warnings = warnings;
+    warnings.extend(overallocation_warnings(
+        &allocations(&pool, db.0.projects()?.iter_all()),
+        &[project_uid],
+    ));
+    let people = project
+        .people
+        .iter()
+        .map(|p| PersonView {
+            uid: p.uid.clone(),
+            name: p.name.clone(),
+            capacity: p.capacity,
+            skills: p.skills.iter().join(" "),
+            start: p.start.map(|d| d.to_string()).unwrap_or_default(),
+            vacations: p
+                .vacations
+                .iter()
+                .map(|v| match v.start == v.end {
+                    true => v.start.to_string(),
+                    false => format!("{} {}", v.start, v.end),
+                })
+                .join("\n"),
+            pool: p.pool.clone().unwrap_or_default(),
+        })
+        .collect_vec();
+    Ok(Some(Template::render(
         "partials/people",
         context! {
             project,
+            people,
+            pool,
+            fresh_id: unique_id(),
+            warnings,
+            can_undo: !undo.undo.is_empty(),
+            can_redo: !undo.redo.is_empty(),
         },
-    ))
+    )))
 }
 
 #[post("/project/<project_uid>/people", data = "<form>")]
 fn post_people(
     project_uid: &str,
-    form: Form<ProjectPeopleForm>,
+    form: Form<PersonForm>,
     db: &State<Db>,
-) -> AnyResult<Template> {
+) -> AnyResult<Option<Template>> {
+    let mut warnings = vec![];
+    let parse_date =
+        |s: &str, warnings: &mut Vec<String>| match NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+            Ok(date) => Some(date),
+            Err(_) => {
+                warnings.push(format!("{} is not a date, use YYYY-MM-DD", s));
+                None
+            }
+        };
+
+    let capacity = match form.capacity.trim().parse::<f64>() {
+        Ok(c) if c.is_finite() && c >= 0.0 => Some(c),
+        _ => {
+            warnings.push(format!("{} is not a capacity, use e.g. 0.5", form.capacity));
+            None
+        }
+    };
+    let start = match form.start.trim() {
+        "" => Some(None),
+        s => parse_date(s, &mut warnings).map(Some),
+    };
+    let mut vacations = vec![];
+    for line in form.vacations.lines() {
+        // Anything that isn't a date, like "-" or "to", separates the two dates
+        let dates = line
+            .split_whitespace()
+            .filter(|w| w.chars().any(|c| c.is_ascii_digit()))
+            .filter_map(|w| parse_date(w, &mut warnings))
+            .collect_vec();
+        match dates[..] {
+            [] => {}
+            [day] => vacations.push(Vacation {
+                start: day,
+                end: day,
+            }),
+            [a, b, ..] => vacations.push(Vacation {
+                start: a.min(b),
+                end: a.max(b),
+            }),
+        }
+    }
+    vacations.sort();
+
+    db.0.with_project(project_uid, |project| {
+        let i = match project.people.iter().position(|p| p.uid == form.uid) {
+            Some(i) => i,
+            None => {
+                project.people.push(Person {
+                    uid: form.uid.clone(),
+                    ..Default::default()
+                });
+                project.people.len() - 1
+            }
+        };
+        let person = &mut project.people[i];
+        person.name = form.name.trim().to_owned();
+        // Keep the old values rather than losing them to a typo
+        if let Some(capacity) = capacity {
+            person.capacity = capacity;
+        }
+        person.skills = parse_skills(&form.skills);
+        if let Some(start) = start {
+            person.start = start;
+        }
+        person.vacations = vacations.clone();
+        if let Some(pool) = form.pool.as_deref().map(str::trim) {
+            person.pool = (!pool.is_empty()).then(|| pool.to_owned());
+        }
+        project.ensure_people();
+    })?;
+    render_people(project_uid, warnings, db)
+}
+
+#[delete("/project/<project_uid>/people/<uid>")]
+fn delete_person(project_uid: &str, uid: &str, db: &State<Db>) -> AnyResult<Option<Template>> {
     db.0.with_project(project_uid, |project| {
-        if form.people > 0 {
-            project.people = form.people;
+        project.people.retain(|p| p.uid != uid);
+        project.ensure_people();
+    })?;
+    render_people(project_uid, vec![], db)
+}
+
+#[get("/pool")]
+fn get_pool() -> Template {
+    Template::render("pool", context! {})
+}
+
+#[get("/pool/people")]
+fn get_pool_people(db: &State<Db>) -> AnyResult<Template> {
+    render_pool(vec![], db)
+}
+
+fn render_pool(warnings: Vec<String>, db: &State<Db>) -> AnyResult<Template> {
+    let projects = db.0.projects()?;
+    let allocations = allocations(&db.0.pool()?, projects.iter_all());
+    let mut warnings = warnings;
+    warnings.extend(overallocation_warnings(
+        &allocations,
+        &projects.iter_all().map(|p| p.uid.as_str()).collect_vec(),
+    ));
+    Ok(Template::render(
+        "partials/pool",
+        context! {
+            allocations,
+            fresh_id: unique_id(),
+            warnings,
+        },
+    ))
+}
+
+#[post("/pool", data = "<form>")]
+fn post_pool(form: Form<PoolMemberForm>, db: &State<Db>) -> AnyResult<Template> {
+    let mut warnings = vec![];
+    let capacity = match form.capacity.trim().parse::<f64>() {
+        Ok(c) if c.is_finite() && c >= 0.0 => Some(c),
+        _ => {
+            warnings.push(format!("{} is not a capacity, use e.g. 0.5", form.capacity));
+            None
+        }
+    };
+    db.0.with_pool(|pool| {
+        let i = match pool.iter().position(|m| m.uid == form.uid) {
+            Some(i) => i,
+            None => {
+                pool.push(PoolMember {
+                    uid: form.uid.clone(),
+                    ..Default::default()
+                });
+                pool.len() - 1
+            }
+        };
+        let member = &mut pool[i];
+        member.name = match form.name.trim() {
+            "" => format!("Person {}", i + 1),
+            name => name.to_owned(),
+        };
+        // Keep the old value rather than losing it to a typo
+        if let Some(capacity) = capacity {
+            member.capacity = capacity;
         }
     })?;
-    get_people(project_uid, db)
+    render_pool(warnings, db)
+}
+
+/// People on projects who were this member only work on their project from then on
+#[delete("/pool/<uid>")]
+fn delete_pool_member(uid: &str, db: &State<Db>) -> AnyResult<Template> {
+    db.0.with_pool(|pool| pool.retain(|m| m.uid != uid))?;
+    render_pool(vec![], db)
+}
+
+#[get("/project/<project_uid>/calendar")]
+fn get_calendar(project_uid: &str, db: &State<Db>) -> AnyResult<Option<Template>> {
+    render_calendar(project_uid, vec![], db)
+}
+
+fn render_calendar(
+    project_uid: &str,
+    warnings: Vec<String>,
+    db: &State<Db>,
+) -> AnyResult<Option<Template>> {
+    let Some(project) = db.0.project(project_uid)? else {
+        return Ok(None);
+    };
+    let undo = db.0.undo_stacks(project_uid)?;
+    let weekdays = (0..7)
+        .map(|i| Weekday::try_from(i as u8).unwrap())
+        .map(|d| Choice {
+            value: d.to_string(),
+            caption: d.to_string(),
+        })
+        .collect_vec();
+    let working_days = project
+        .calendar
+        .working_days
+        .iter()
+        .map(|d| d.to_string())
+        .collect_vec();
+    let holidays = project.calendar.holidays.iter().join("\n");
+    Ok(Some(Template::render(
+        "partials/calendar",
+        context! {
+            project,
+            weekdays,
+            working_days,
+            holidays,
+            warnings,
+            can_undo: !undo.undo.is_empty(),
+            can_redo: !undo.redo.is_empty(),
+        },
+    )))
+}
+
+#[post("/project/<project_uid>/calendar", data = "<form>")]
+fn post_calendar(
+    project_uid: &str,
+    form: Form<CalendarForm>,
+    db: &State<Db>,
+) -> AnyResult<Option<Template>> {
+    let mut warnings = vec![];
+    let start = match form.start.trim() {
+        "" => None,
+        s => match NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+            Ok(date) => Some(date),
+            Err(_) => {
+                warnings.push(format!("{} is not a date", s));
+                None
+            }
+        },
+    };
+    let working_days = form
+        .working_days
+        .iter()
+        .filter_map(|d| d.parse::<Weekday>().ok())
+        .sorted_by_key(|d| d.num_days_from_monday())
+        .dedup()
+        .collect_vec();
+    let mut holidays = BTreeSet::new();
+    for line in form.holidays.lines() {
+        let Some(word) = line.split_whitespace().next() else {
+            continue;
+        };
+        match NaiveDate::parse_from_str(word, "%Y-%m-%d") {
+            Ok(date) => {
+                holidays.insert(date);
+            }
+            Err(_) => warnings.push(format!("{} is not a date, use YYYY-MM-DD", word)),
+        }
+    }
+
+    db.0.with_project(project_uid, |project| {
+        // Keep the old start date rather than losing it to a typo
+        if start.is_some() || form.start.trim().is_empty() {
+            project.calendar.start = start;
+        }
+        project.calendar.working_days = working_days.clone();
+        project.calendar.holidays = holidays.clone();
+    })?;
+    render_calendar(project_uid, warnings, db)
+}
+
+#[post("/project/<project_uid>/seed", data = "<form>")]
+fn post_seed(
+    project_uid: &str,
+    form: Form<ProjectSeedForm>,
+    db: &State<Db>,
+) -> AnyResult<Option<Template>> {
+    // Small enough to read out loud, and to survive a round trip through JavaScript
+    let seed = form.seed.unwrap_or_else(|| rand::random::<u32>() as u64);
+    db.0.with_project(project_uid, |project| {
+        project.seed = seed;
+    })?;
+    get_forecast(project_uid, db)
+}
+
+#[post("/project/<project_uid>/iterations", data = "<form>")]
+fn post_iterations(
+    project_uid: &str,
+    form: Form<ProjectIterationsForm>,
+    db: &State<Db>,
+) -> AnyResult<Option<Template>> {
+    let tolerance = form
+        .tolerance
+        .as_ref()
+        .and_then(|t| t.parse::<f64>().ok())
+        .filter(|t| *t > 0.0)
+        .map(|t| t / 100.0);
+    db.0.with_project(project_uid, |project| {
+        project.iterations = form.iterations.clamp(1, MAX_ITERATIONS);
+        project.tolerance = tolerance;
+    })?;
+    get_forecast(project_uid, db)
+}
+
+#[post("/project/<project_uid>/confidence", data = "<form>")]
+fn post_confidence(
+    project_uid: &str,
+    form: Form<ProjectConfidenceForm>,
+    db: &State<Db>,
+) -> AnyResult<Option<Template>> {
+    if let Some(confidence) = form.confidence.filter(|c| c.is_finite()) {
+        db.0.with_project(project_uid, |project| {
+            project.confidence = (confidence / 100.0).clamp(0.0, 1.0);
+        })?;
+    }
+    get_forecast(project_uid, db)
+}
+
+#[post("/project/<project_uid>/scheduling", data = "<form>")]
+fn post_scheduling(
+    project_uid: &str,
+    form: Form<ProjectSchedulingForm>,
+    db: &State<Db>,
+) -> AnyResult<Option<Template>> {
+    db.0.with_project(project_uid, |project| {
+        project.scheduling = form.scheduling;
+    })?;
+    get_forecast(project_uid, db)
+}
+
+#[post("/project/<project_uid>/efficiency-loss", data = "<form>")]
+fn post_efficiency_loss(
+    project_uid: &str,
+    form: Form<ProjectEfficiencyLossForm>,
+    db: &State<Db>,
+) -> AnyResult<Option<Template>> {
+    if let Some(loss) = form.efficiency_loss.filter(|l| l.is_finite()) {
+        db.0.with_project(project_uid, |project| {
+            project.efficiency_loss = (loss / 100.0).max(0.0);
+        })?;
+    }
+    get_forecast(project_uid, db)
 }
 
 #[get("/project/<project_uid>/forecast")]
@@ -188,18 +721,38 @@ fn get_forecast(project_uid: &str, db: &State<Db>) -> AnyResult<Option<Template>
         return Ok(None);
     };
 
-    let tasks = db.0.tasks()?.into_many(project_uid).collect_vec();
+    let undo = db.0.undo_stacks(project_uid)?;
+    let ProjectPlan {
+        project,
+        tasks,
+        upstream,
+        allocations,
+    } = project_plan(project, &db.0.projects()?, &db.0.tasks()?, &db.0.pool()?);
     let sorted_tasks = roughly_sort_tasks(tasks.iter());
-    if !sorted_tasks.cycles.is_empty() {
-        return Ok(Some(Template::render(
-            "partials/forecast",
-            context! {
-                cycles: sorted_tasks.cycles
-            },
-        )));
-    }
-
-    let rs = simulate_tasks(tasks.into_iter(), project.people);
+    let rs = match sorted_tasks.cycles.is_empty() {
+        true => simulate_project(&project, &tasks, &upstream, today()),
+        false => Err(Cycles(sorted_tasks.cycles.clone())),
+    };
+    let rs = match rs {
+        Ok(rs) => rs,
+        Err(Cycles(cycles)) => {
+            return Ok(Some(Template::render(
+                "partials/forecast",
+                context! {
+                    project,
+                    cycles,
+                    can_undo: !undo.undo.is_empty(),
+                    can_redo: !undo.redo.is_empty(),
+                },
+            )));
+        }
+    };
+    let mut warnings = overallocation_warnings(&allocations, &[project_uid]);
+    warnings.extend(estimate_warnings(tasks.iter()));
+    warnings.extend(staffing_warnings(
+        &HashMap::from_iter(tasks.iter().map(|t| (t.uid.as_str(), t))),
+        &rs.unstaffed,
+    ));
     #[derive(Serialize)]
     struct TaskPrediction {
         task: Task,
@@ -208,8 +761,24 @@ fn get_forecast(project_uid: &str, db: &State<Db>) -> AnyResult<Option<Template>
         p50_start: OrderedFloat<f64>,
         p50_finish: OrderedFloat<f64>,
         p90_finish: OrderedFloat<f64>,
+        p50_finish_date: Option<NaiveDate>,
+        p90_finish_date: Option<NaiveDate>,
+        /// In percent
+        criticality: f64,
+        /// Working days into the project, only for milestones with a deadline
+        deadline: Option<f64>,
+        /// In percent
+        deadline_chance: Option<f64>,
+        /// Less likely to make the deadline than the project asks for
+        deadline_at_risk: bool,
+        /// Spans its subtasks
+        epic: bool,
     }
-    let task_timeline: Vec<TaskPrediction> = sorted_tasks
+    let epics = children(&HashMap::from_iter(
+        tasks.iter().map(|t| (t.uid.as_str(), t)),
+    ));
+    let mut deadline_chances = vec![];
+    let mut task_timeline: Vec<TaskPrediction> = sorted_tasks
         .sorted_tasks
         .iter()
         .map(|task| {
@@ -225,6 +794,9 @@ fn get_forecast(project_uid: &str, db: &State<Db>) -> AnyResult<Option<Template>
 
             println!("Task {} {:?} {}", task.id, full_rng, p50_finish);
 
+            let deadline_chance = rs.deadline_chance.get(&task.uid).copied();
+            deadline_chances.push((task, deadline_chance));
+
             TaskPrediction {
                 task: task.clone(),
                 full_svg: render_dist(&rng.start, &rng.end, &full_rng).render(),
@@ -232,41 +804,249 @@ fn get_forecast(project_uid: &str, db: &State<Db>) -> AnyResult<Option<Template>
                 p50_start: p50_start.into(),
                 p50_finish: p50_finish.into(),
                 p90_finish: p90_finish.into(),
+                p50_finish_date: project.calendar.finish_date(p50_finish),
+                p90_finish_date: project.calendar.finish_date(p90_finish),
+                criticality: (rs.criticality[&task.uid] * 100.0).round(),
+                deadline: deadline_chance
+                    .and(task.deadline)
+                    .and_then(|d| d.offset(&project.calendar)),
+                deadline_chance: deadline_chance.map(|c| (c * 100.0).round()),
+                deadline_at_risk: deadline_chance.is_some_and(|c| c < project.confidence),
+                epic: epics.contains_key(task.uid.as_str()),
             }
         })
         .sorted_by_key(|x| x.p50_start)
         .collect();
 
-    let time_range = task_timeline.iter().fold(0..0, |r, x| {
+    warnings.extend(deadline_warnings(&project, deadline_chances.into_iter()));
+
+    // Deadlines after every simulated finish still show on the ruler, up to a point,
+    // beyond that only their chance shows
+    let simulated = task_timeline.iter().fold(0..0, |r, x| {
         min(r.start, x.full_rng.start)..max(r.end, x.full_rng.end)
     });
+    let limit = max(simulated.end.saturating_mul(2), simulated.end + 20);
+    for x in &mut task_timeline {
+        x.deadline = x.deadline.filter(|d| *d < limit as f64);
+    }
+    let time_range = task_timeline.iter().fold(simulated, |r, x| {
+        let deadline = x.deadline.map_or(0, |d| d.ceil() as u32 + 1);
+        r.start..max(r.end, deadline)
+    });
+
+    // The estimates that move the finish most, strongest first
+    let sensitive_tasks = sorted_tasks
+        .sorted_tasks
+        .iter()
+        .map(|t| (t, rs.sensitivity[&t.uid]))
+        .filter(|(_, c)| c.abs() >= 0.01)
+        .sorted_by_key(|(_, c)| Reverse(OrderedFloat(c.abs())))
+        .take(15)
+        .collect_vec();
+    let tornado = (!sensitive_tasks.is_empty())
+        .then(|| render_tornado(&sensitive_tasks.iter().map(|(_, c)| *c).collect_vec()).render());
+    let tornado_rows = sensitive_tasks
+        .iter()
+        .map(|(t, c)| Choice {
+            value: format!("{:+.2}", c),
+            caption: format!("{} {}", t.id, t.title),
+        })
+        .collect_vec();
 
     let p50_finish = task_timeline.iter().map(|t| t.p50_finish).max();
     let p90_finish = task_timeline.iter().map(|t| t.p90_finish).max();
+    let p50_finish_date = p50_finish.and_then(|f| project.calendar.finish_date(*f));
+    let p90_finish_date = p90_finish.and_then(|f| project.calendar.finish_date(*f));
+    let ruler = ruler(time_range, &project.calendar);
 
     Ok(Some(Template::render(
         "partials/forecast",
         context! {
+            project,
+            iterations: rs.iterations,
             p50_finish,
             p90_finish,
+            p50_finish_date,
+            p90_finish_date,
             task_timeline,
-            time_range: time_range.collect_vec(),
+            ruler,
+            tornado,
+            tornado_rows,
+            tornado_row: TORNADO_ROW,
+            warnings,
+            can_undo: !undo.undo.is_empty(),
+            can_redo: !undo.redo.is_empty(),
+        },
+    )))
+}
+
+/// Label every fifth day, or the first working day of every week when there are dates
+fn ruler(days: Range<u32>, calendar: &Calendar) -> Vec<RulerTick> {
+    let mut dates = calendar.working_days_from(days.start as usize);
+    let mut previous: Option<NaiveDate> = None;
+    days.map(|day| {
+        let label = match dates.next() {
+            Some(date) => {
+                let new_week = previous.is_none_or(|p| p.iso_week() != date.iso_week());
+                previous = Some(date);
+                new_week.then(|| date.format("%-d %b").to_string())
+            }
+            None => (day % 5 == 0).then(|| day.to_string()),
+        };
+        RulerTick { day, label }
+    })
+    .collect()
+}
+
+#[get("/project/<project_uid>/snapshots")]
+fn get_snapshots(project_uid: &str, db: &State<Db>) -> AnyResult<Option<Template>> {
+    let Some(project) = db.0.project(project_uid)? else {
+        return Ok(None);
+    };
+    let snapshots = db.0.snapshots(project_uid)?;
+    Ok(Some(Template::render(
+        "partials/snapshots",
+        context! {
+            project,
+            snapshots,
+        },
+    )))
+}
+
+#[post("/project/<project_uid>/snapshots", data = "<form>")]
+fn post_snapshot(
+    project_uid: &str,
+    form: Form<SnapshotForm>,
+    db: &State<Db>,
+) -> AnyResult<Option<Template>> {
+    if !form.name.is_empty() {
+        db.0.create_snapshot(project_uid, &form.name)?;
+    }
+    get_snapshots(project_uid, db)
+}
+
+/// Compare the forecasts of two snapshots, or a snapshot and the current plan
+///
+/// `a` and `b` are snapshot uids, or "current" for the current state of the project.
+#[get("/project/<project_uid>/compare?<a>&<b>")]
+fn get_compare(project_uid: &str, a: &str, b: &str, db: &State<Db>) -> AnyResult<Option<Template>> {
+    let load_plan = |uid: &str| -> anyhow::Result<Option<Plan>> {
+        if uid == "current" {
+            let Some(project) = db.0.project(project_uid)? else {
+                return Ok(None);
+            };
+            // The same as the Forecast tab, but with the project as planned to describe changes
+            let ProjectPlan {
+                tasks,
+                upstream,
+                allocations,
+                ..
+            } = project_plan(
+                project.clone(),
+                &db.0.projects()?,
+                &db.0.tasks()?,
+                &db.0.pool()?,
+            );
+            return Ok(Some(Plan {
+                name: "Current plan".to_owned(),
+                project,
+                tasks,
+                today: today(),
+                upstream,
+                allocations,
+            }));
+        }
+        Ok(db.0.snapshot(project_uid, uid)?.map(|s| Plan {
+            name: s.name,
+            project: s.project,
+            tasks: s.tasks,
+            today: s.created.date_naive(),
+            upstream: vec![],
+            allocations: vec![],
+        }))
+    };
+    let (Some(before), Some(after)) = (load_plan(a)?, load_plan(b)?) else {
+        return Ok(None);
+    };
+
+    let comparison = compare_plans(&before, &after);
+    Ok(Some(Template::render(
+        "partials/compare",
+        context! {
+            comparison,
         },
     )))
 }
 
+/// A number of days, anything else clears the field
+fn parse_days(x: &str) -> Option<f64> {
+    x.trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|d| d.is_finite() && *d >= 0.0)
+}
+
 #[post("/project/<project_uid>/tasks", data = "<form>")]
 fn post_tasks(project_uid: &str, form: Form<TaskForm>, db: &State<Db>) -> AnyResult<Template> {
-    // Convert add_dependency input (id) into a uid
-    let mut add_dependencies: Vec<String> = vec![];
-    if let Some(add) = &form.add_dependency {
-        if !add.is_empty() {
-            if let Some(t) = db.0.tasks()?.into_many(project_uid).find(|t| &t.id == add) {
-                add_dependencies.push(t.uid)
+    let existing = db.0.task(project_uid, &form.uid)?;
+
+    // Convert add_dependency input (id, maybe with a kind and lag like `A:SS+2`) into a uid,
+    // `Project/ID` is a task of another project
+    let mut add_dependencies: Vec<(String, Dependency)> = vec![];
+    if let Some(add) = form.add_dependency.as_deref().filter(|a| !a.is_empty()) {
+        if let Ok((id, dependency)) = parse_dependency(add) {
+            let tasks = db.0.tasks()?;
+            if let Some(t) = find_task(id, project_uid, &db.0.projects()?, &tasks) {
+                // Adding a task that is already a dependency keeps its kind, unless a new one is given
+                let known = existing
+                    .as_ref()
+                    .is_some_and(|e| e.dependencies.contains_key(&t.uid));
+                if add.contains(':') || !known {
+                    let project = (t.project_uid != project_uid).then(|| t.project_uid.clone());
+                    add_dependencies.push((
+                        t.uid.clone(),
+                        Dependency {
+                            project,
+                            ..dependency
+                        },
+                    ));
+                }
             }
         }
     }
 
+    // Fill in today when a task starts or is done, unless a date was given
+    let parse_date = |d: &Option<String>| {
+        d.as_ref()
+            .map(|d| NaiveDate::parse_from_str(d.trim(), "%Y-%m-%d").ok())
+    };
+    let mut started = parse_date(&form.started);
+    let mut finished = parse_date(&form.finished);
+    let status = form.status.unwrap_or_default();
+    let has_date =
+        |new: Option<Option<NaiveDate>>, old: Option<NaiveDate>| new.unwrap_or(old).is_some();
+    if status != TaskStatus::Todo && !has_date(started, existing.as_ref().and_then(|t| t.started)) {
+        started = Some(Some(today()));
+    }
+    if status == TaskStatus::Done && !has_date(finished, existing.as_ref().and_then(|t| t.finished))
+    {
+        finished = Some(Some(today()));
+    }
+
+    // The epic is given by ID, one that would make the task part of itself is ignored
+    let parent = match form.parent.as_deref().map(str::trim) {
+        None => None,
+        Some("") => Some(None),
+        Some(id) => {
+            let tasks = db.0.project_tasks(project_uid)?;
+            let by_uid = HashMap::from_iter(tasks.iter().map(|t| (t.uid.as_str(), t)));
+            tasks
+                .iter()
+                .find(|t| t.id == id && !is_part_of(&by_uid, &t.uid, &form.uid))
+                .map(|t| Some(t.uid.clone()))
+        }
+    };
+
     {
         db.0.upsert_task(TaskUpdate {
             project_uid: project_uid.to_owned(),
@@ -274,15 +1054,92 @@ fn post_tasks(project_uid: &str, form: Form<TaskForm>, db: &State<Db>) -> AnyRes
             r#type: Some(form.r#type.clone()),
             id: Some(form.id.clone()),
             title: Some(form.title.clone()),
-            estimate: form.estimate.as_ref().map(|x| x.parse::<f64>().ok()),
+            estimate: form.estimate.as_deref().map(parse_days),
             risk: form.risk.as_ref().map(|risk| Some(*risk)),
+            distribution: form.distribution,
+            optimistic: form.optimistic.as_deref().map(parse_days),
+            pessimistic: form.pessimistic.as_deref().map(parse_days),
+            sigma: form.sigma.as_deref().map(parse_days),
             add_dependencies,
             remove_dependencies: vec![],
+            skills: form.skills.as_deref().map(parse_skills),
+            assignee: form
+                .assignee
+                .as_ref()
+                .map(|a| Some(a.clone()).filter(|a| !a.is_empty())),
+            status: form.status,
+            started,
+            finished,
+            remaining: form.remaining.as_deref().map(parse_days),
+            // Keep the old deadline rather than losing it to a typo
+            deadline: form.deadline.as_ref().and_then(|d| match d.trim() {
+                "" => Some(None),
+                d => d.parse::<Deadline>().ok().map(Some),
+            }),
+            parent,
+            priority: form.priority.as_ref().map(|x| x.trim().parse::<u32>().ok()),
+            max_people: form
+                .max_people
+                .as_ref()
+                .map(|x| x.trim().parse::<u32>().ok().filter(|m| *m > 0)),
         })?;
     }
     get_tasks(project_uid, db)
 }
 
+#[get("/project/<project_uid>/tasks.csv")]
+fn get_tasks_csv(project_uid: &str, db: &State<Db>) -> AnyResult<Option<CsvDownload>> {
+    let Some(project) = db.0.project(project_uid)? else {
+        return Ok(None);
+    };
+    let tasks = db.0.project_tasks(project_uid)?;
+    // Header values have to be plain ASCII
+    let name: String = project
+        .name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | ' ' | '-' | '_' => c,
+            _ => '_',
+        })
+        .collect();
+    let filename = if name.trim().is_empty() {
+        "tasks.csv".to_owned()
+    } else {
+        format!("{}.csv", name.trim())
+    };
+    Ok(Some(CsvDownload(
+        export_tasks(&tasks, &project.people)?,
+        Header::new(
+            "Content-Disposition",
+            format!("attachment; filename=\"{}\"", filename),
+        ),
+    )))
+}
+
+#[post("/project/<project_uid>/tasks/import", data = "<form>")]
+async fn post_tasks_import(
+    project_uid: &str,
+    form: Form<CsvImportForm<'_>>,
+    db: &State<Db>,
+) -> AnyResult<Option<Template>> {
+    let mut data = String::new();
+    form.file
+        .open()
+        .await
+        .map_err(anyhow::Error::from)?
+        .read_to_string(&mut data)
+        .await
+        .map_err(anyhow::Error::from)?;
+
+    let Some(project) = db.0.project(project_uid)? else {
+        return Ok(None);
+    };
+    let tasks = db.0.project_tasks(project_uid)?;
+    let (updates, report) = import_tasks(project_uid, &tasks, &project.people, &data);
+    db.0.upsert_tasks(updates)?;
+    task_grid(project_uid, db, Some(report)).map(Some)
+}
+
 #[delete("/project/<project_uid>/tasks/<uid>")]
 fn delete_task(project_uid: &str, uid: &str, db: &State<Db>) -> AnyResult<Template> {
     {
@@ -304,26 +1161,140 @@ fn delete_dep(
     get_tasks(project_uid, db)
 }
 
+/// Change the kind and lag of a dependency, a typo leaves it alone
+#[post(
+    "/project/<project_uid>/tasks/<task_uid>/dep/<dep_uid>",
+    data = "<form>"
+)]
+fn post_dep(
+    project_uid: &str,
+    task_uid: &str,
+    dep_uid: &str,
+    form: Form<DependencyForm>,
+    db: &State<Db>,
+) -> AnyResult<Template> {
+    if let Ok(dependency) = form.link.parse::<Dependency>() {
+        db.0.with_task(project_uid, task_uid, |task| {
+            if let Some(d) = task.dependencies.get_mut(dep_uid) {
+                *d = Dependency {
+                    project: d.project.take(),
+                    ..dependency.clone()
+                };
+            }
+        })?;
+    }
+    get_tasks(project_uid, db)
+}
+
+#[post("/project/<project_uid>/undo")]
+fn post_undo(project_uid: &str, db: &State<Db>) -> AnyResult<TabPartial> {
+    let change = db.0.undo(project_uid)?;
+    render_changed(project_uid, change, db)
+}
+
+#[post("/project/<project_uid>/redo")]
+fn post_redo(project_uid: &str, db: &State<Db>) -> AnyResult<TabPartial> {
+    let change = db.0.redo(project_uid)?;
+    render_changed(project_uid, change, db)
+}
+
+/// Show the tab with what an undone or redone change touched
+fn render_changed(
+    project_uid: &str,
+    change: Option<Change>,
+    db: &State<Db>,
+) -> AnyResult<TabPartial> {
+    let tab = match &change {
+        Some(Change::Project { before, after }) if before.people != after.people => "People",
+        Some(Change::Project { before, after }) if before.calendar != after.calendar => "Calendar",
+        Some(Change::Project { before, after }) if before.name == after.name => "Forecast",
+        _ => "Tasks",
+    };
+    let template = match tab {
+        "People" => render_people(project_uid, vec![], db)?,
+        "Calendar" => render_calendar(project_uid, vec![], db)?,
+        "Forecast" => get_forecast(project_uid, db)?,
+        _ => Some(get_tasks(project_uid, db)?),
+    };
+    let header = match &change {
+        // The name is in the page header, outside of the tabs
+        Some(Change::Project { before, after }) if before.name != after.name => {
+            Header::new("HX-Refresh", "true")
+        }
+        _ => Header::new("HX-Trigger", format!("select{}Tab", tab)),
+    };
+    Ok(TabPartial(template, header))
+}
+
 #[launch]
 fn rocket() -> _ {
-    rocket::build()
+    let rocket = rocket::build();
+
+    // Configure with ROCKET_DATABASE=planny.sqlite (or in Rocket.toml) to use SQLite
+    let filename: String = rocket
+        .figment()
+        .extract_inner("database")
+        .unwrap_or_else(|_| "db.json".to_owned());
+    // Keep the database in memory with ROCKET_DATABASE_CACHE=true
+    let cached: bool = rocket
+        .figment()
+        .extract_inner("database_cache")
+        .unwrap_or(false);
+
+    rocket
         .mount(
             "/",
             routes![
                 index,
+                get_portfolio,
                 get_tasks,
                 post_tasks,
+                get_tasks_csv,
+                post_tasks_import,
                 delete_task,
                 delete_dep,
+                post_dep,
                 get_project,
                 post_project_name,
                 create_project,
                 get_people,
                 post_people,
+                delete_person,
+                get_pool,
+                get_pool_people,
+                post_pool,
+                delete_pool_member,
                 get_forecast,
+                get_calendar,
+                post_calendar,
+                post_seed,
+                post_iterations,
+                post_confidence,
+                post_scheduling,
+                post_efficiency_loss,
+                post_undo,
+                post_redo,
+                get_snapshots,
+                post_snapshot,
+                get_compare,
             ],
         )
+        .mount("/api/v1", api::routes())
         .mount("/s", FileServer::from(relative!("/static")))
         .attach(Template::fairing())
-        .manage(Db(Database::new("db.json".to_string()).unwrap()))
+        .manage(Db(Database::new(filename, cached).unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn days_in_the_form_are_finite_and_not_negative() {
+        assert_eq!(parse_days(" 2.5 "), Some(2.5));
+        assert_eq!(parse_days("0"), Some(0.0));
+        for bad in ["", "-1", "inf", "NaN", "two"] {
+            assert_eq!(parse_days(bad), None, "{}", bad);
+        }
+    }
 }
@@ -0,0 +1,110 @@
+//! People of the organisation who are shared by several projects
+use itertools::Itertools;
+use serde::Serialize;
+
+use crate::datamodel::{PoolMember, Project};
+
+/// How much of the time of someone in the pool the projects count on
+#[derive(Serialize, Debug, Clone)]
+pub struct Allocation {
+    pub member: PoolMember,
+    pub projects: Vec<ProjectShare>,
+    /// The shares of all projects together
+    pub total: f64,
+    /// The projects count on more time than they have
+    pub overallocated: bool,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ProjectShare {
+    pub uid: String,
+    pub name: String,
+    /// Fraction of a full-time week
+    pub share: f64,
+}
+
+impl Allocation {
+    /// What each project gets of the share it counts on
+    pub fn fraction(&self) -> f64 {
+        match self.overallocated {
+            true => self.member.capacity / self.total,
+            false => 1.0,
+        }
+    }
+}
+
+/// The allocation of everyone in the pool, in the order of the pool
+pub fn allocations<'a>(
+    pool: &[PoolMember],
+    projects: impl IntoIterator<Item = &'a Project>,
+) -> Vec<Allocation> {
+    let projects: Vec<&Project> = projects.into_iter().collect();
+    pool.iter()
+        .map(|member| {
+            let shares: Vec<ProjectShare> = projects
+                .iter()
+                .sorted_by(|a, b| a.name.cmp(&b.name).then(a.uid.cmp(&b.uid)))
+                .filter_map(|project| {
+                    let share: f64 = project
+                        .people
+                        .iter()
+                        .filter(|p| p.pool.as_ref() == Some(&member.uid))
+                        .map(|p| p.capacity)
+                        .sum();
+                    (share > 0.0).then(|| ProjectShare {
+                        uid: project.uid.clone(),
+                        name: project.name.clone(),
+                        share,
+                    })
+                })
+                .collect();
+            let total = shares.iter().fold(0.0, |total, s| total + s.share);
+            Allocation {
+                member: member.clone(),
+                projects: shares,
+                total,
+                overallocated: total > member.capacity + 1e-9,
+            }
+        })
+        .collect()
+}
+
+/// Give the people of a project only their part of the time of someone who is overallocated
+pub fn share_out(project: &mut Project, allocations: &[Allocation]) {
+    for person in &mut project.people {
+        let Some(uid) = &person.pool else {
+            continue;
+        };
+        if let Some(allocation) = allocations.iter().find(|a| a.member.uid == *uid) {
+            person.capacity *= allocation.fraction();
+        }
+    }
+}
+
+/// People that the projects count on more than they can
+pub fn overallocation_warnings(allocations: &[Allocation], project_uids: &[&str]) -> Vec<String> {
+    allocations
+        .iter()
+        .filter(|a| a.overallocated)
+        .filter(|a| {
+            a.projects
+                .iter()
+                .any(|p| project_uids.contains(&p.uid.as_str()))
+        })
+        .map(|a| {
+            let shares: Vec<String> = a
+                .projects
+                .iter()
+                .map(|p| format!("{} {:.0}%", p.name, p.share * 100.0))
+                .collect();
+            format!(
+                "{} is allocated {:.0}% ({}) but works {:.0}%, so each project gets {:.0}% of what it counts on",
+                a.member.name,
+                a.total * 100.0,
+                shares.join(", "),
+                a.member.capacity * 100.0,
+                a.fraction() * 100.0,
+            )
+        })
+        .collect()
+}
@@ -0,0 +1,430 @@
+//! Projects that wait for each other's tasks, forecast together
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use chrono::NaiveDate;
+use itertools::Itertools;
+use serde::Serialize;
+
+use crate::{
+    calendar::Calendar,
+    datamodel::{roughly_sort_tasks, Dependency, PoolMember, Project, Task, TaskType},
+    forecast::{simulate_projects, simulate_tasks, SimulationResults},
+    hstable::HSTable,
+    pool::{allocations, share_out, Allocation},
+    report::{
+        dates, deadline_warnings, estimate_warnings, finish, staffing_warnings, task_forecast,
+        Cycles, TaskForecast,
+    },
+};
+
+#[derive(Serialize, Debug)]
+pub struct PortfolioForecast {
+    /// How many times the projects were simulated
+    pub iterations: u32,
+    /// Everyone worked on every project, instead of only on their own
+    pub shared_people: bool,
+    pub projects: Vec<ProjectForecast>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ProjectForecast {
+    pub uid: String,
+    pub name: String,
+    pub finish: BTreeMap<&'static str, f64>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub finish_dates: BTreeMap<&'static str, NaiveDate>,
+    /// Its milestones, and the tasks that other projects wait for
+    pub milestones: Vec<TaskForecast>,
+}
+
+/// Tells apart the tasks of several projects
+pub fn key(project_uid: &str, uid: &str) -> String {
+    format!("{}/{}", project_uid, uid)
+}
+
+/// The task with its uid, epic and dependencies by `key`, to be simulated with other projects
+pub fn qualified(task: &Task) -> Task {
+    Task {
+        uid: key(&task.project_uid, &task.uid),
+        parent: task.parent.as_ref().map(|p| key(&task.project_uid, p)),
+        dependencies: task
+            .dependencies
+            .iter()
+            .map(|(d, dependency)| {
+                let project = dependency.project.as_deref().unwrap_or(&task.project_uid);
+                let dependency = Dependency {
+                    project: None,
+                    ..dependency.clone()
+                };
+                (key(project, d), dependency)
+            })
+            .collect(),
+        ..task.clone()
+    }
+}
+
+/// Find a task by ID, or one of another project by `Project/ID`, with the project by name or uid
+pub fn find_task<'a>(
+    id: &str,
+    project_uid: &str,
+    projects: &'a HSTable<Project>,
+    tasks: &'a HSTable<Task>,
+) -> Option<&'a Task> {
+    let by_id = |project_uid: &str, id: &str| tasks.get_many(project_uid).find(|t| t.id == id);
+    if let Some(task) = by_id(project_uid, id) {
+        return Some(task);
+    }
+    let (project, id) = id.rsplit_once('/')?;
+    let (project, id) = (project.trim(), id.trim());
+    let other = projects
+        .get1(project)
+        .or_else(|| {
+            projects
+                .iter_all()
+                .find(|p| p.name.trim().eq_ignore_ascii_case(project))
+        })
+        .filter(|p| p.uid != project_uid)?;
+    by_id(&other.uid, id)
+}
+
+/// The projects that a project waits for, directly or through one another, with their tasks
+pub fn upstream(
+    project_uid: &str,
+    projects: &HSTable<Project>,
+    tasks: &HSTable<Task>,
+) -> Vec<(Project, Vec<Task>)> {
+    let mut seen: HashSet<&str> = HashSet::from([project_uid]);
+    let mut ret = vec![];
+    let mut next = vec![project_uid];
+    while let Some(uid) = next.pop() {
+        let waits_for = tasks
+            .get_many(uid)
+            .flat_map(|t| t.dependencies.values())
+            .filter_map(|d| d.project.as_deref())
+            .sorted()
+            .dedup()
+            .collect_vec();
+        for other in waits_for {
+            if let Some(project) = projects.get1(other).filter(|_| seen.insert(other)) {
+                ret.push((project.clone(), tasks.get_many(other).cloned().collect()));
+                next.push(other);
+            }
+        }
+    }
+    ret
+}
+
+/// The chosen projects with their tasks, followed by the projects they wait for
+///
+/// A task would otherwise not wait for the tasks of projects that were left out.
+pub fn plans(
+    project_uids: &[&str],
+    projects: &HSTable<Project>,
+    tasks: &HSTable<Task>,
+) -> Vec<(Project, Vec<Task>)> {
+    let mut ret: Vec<(Project, Vec<Task>)> = project_uids
+        .iter()
+        .unique()
+        .filter_map(|uid| projects.get1(*uid))
+        .map(|p| (p.clone(), tasks.get_many(&p.uid).cloned().collect()))
+        .collect();
+    for uid in project_uids {
+        for plan in upstream(uid, projects, tasks) {
+            if !ret.iter().any(|(p, _)| p.uid == plan.0.uid) {
+                ret.push(plan);
+            }
+        }
+    }
+    ret
+}
+
+/// A project ready to forecast, the way the Forecast tab does
+pub struct ProjectPlan {
+    /// Everyone it shares with other projects only has their part of their time
+    pub project: Project,
+    pub tasks: Vec<Task>,
+    /// The projects it waits for, with their people shared out the same way
+    pub upstream: Vec<(Project, Vec<Task>)>,
+    pub allocations: Vec<Allocation>,
+}
+
+/// Get a project ready to forecast with `simulate_project`
+pub fn project_plan(
+    mut project: Project,
+    projects: &HSTable<Project>,
+    tasks: &HSTable<Task>,
+    pool: &[PoolMember],
+) -> ProjectPlan {
+    let allocations = allocations(pool, projects.iter_all());
+    share_out(&mut project, &allocations);
+    let mut upstream = upstream(&project.uid, projects, tasks);
+    for (p, _) in &mut upstream {
+        share_out(p, &allocations);
+    }
+    ProjectPlan {
+        tasks: tasks.get_many(&project.uid).cloned().collect(),
+        project,
+        upstream,
+        allocations,
+    }
+}
+
+/// Simulate a project together with the projects it waits for, each worked on by its own people
+///
+/// The results are for the tasks of the project, by uid.
+pub fn simulate_project(
+    project: &Project,
+    tasks: &[Task],
+    upstream: &[(Project, Vec<Task>)],
+    today: NaiveDate,
+) -> Result<SimulationResults, Cycles> {
+    if upstream.is_empty() {
+        return Ok(simulate_tasks(tasks.iter().cloned(), project, today));
+    }
+    let projects = [project.clone()]
+        .into_iter()
+        .chain(upstream.iter().map(|(p, _)| p.clone()))
+        .collect_vec();
+    let names = project_names(&projects);
+    let all = tasks
+        .iter()
+        .map(qualified)
+        .chain(
+            upstream
+                .iter()
+                .flat_map(|(_, tasks)| tasks.iter().map(|t| with_project_id(qualified(t), &names))),
+        )
+        .collect_vec();
+    check_cycles(&all)?;
+    let rs = simulate_projects(all.into_iter(), &projects, false, Some(&project.uid), today);
+    Ok(of_project(rs, &project.uid))
+}
+
+/// Simulate several projects together, on the calendar and with the settings of the first one
+///
+/// With `shared_people` anyone may work on any of the projects, otherwise
+/// everyone sticks to their own.
+pub fn forecast(
+    plans: Vec<(Project, Vec<Task>)>,
+    shared_people: bool,
+    today: NaiveDate,
+) -> Result<PortfolioForecast, Cycles> {
+    let (projects, tasks): (Vec<Project>, Vec<Vec<Task>>) = plans.into_iter().unzip();
+    let Some(first) = projects.first() else {
+        return Ok(PortfolioForecast {
+            iterations: 0,
+            shared_people,
+            projects: vec![],
+            warnings: vec![],
+        });
+    };
+    let calendar = &first.calendar;
+
+    // IDs are only unique within a project
+    let names = project_names(&projects);
+    let all = tasks
+        .iter()
+        .flatten()
+        .map(|t| with_project_id(qualified(t), &names))
+        .collect_vec();
+    check_cycles(&all)?;
+    let rs = simulate_projects(all.iter().cloned(), &projects, shared_people, None, today);
+
+    let by_key: HashMap<&str, &Task> = HashMap::from_iter(all.iter().map(|t| (t.uid.as_str(), t)));
+    let waited_for: HashSet<&str> = all
+        .iter()
+        .flat_map(|t| t.dependencies.keys().map(move |d| (t, d)))
+        .filter(|(t, d)| {
+            by_key
+                .get(d.as_str())
+                .is_some_and(|d| d.project_uid != t.project_uid)
+        })
+        .map(|(_, d)| d.as_str())
+        .collect();
+    let mut warnings = estimate_warnings(all.iter());
+    warnings.extend(staffing_warnings(&by_key, &rs.unstaffed));
+
+    let projects = projects
+        .iter()
+        .zip(&tasks)
+        .map(|(project, tasks)| {
+            let sorted_tasks = roughly_sort_tasks(tasks.iter()).sorted_tasks;
+            let forecasts = sorted_tasks
+                .iter()
+                .map(|t| {
+                    (
+                        t,
+                        task_forecast(t, &key(&project.uid, &t.uid), &rs, calendar),
+                    )
+                })
+                .collect_vec();
+            warnings.extend(deadline_warnings(
+                project,
+                forecasts.iter().map(|(t, f)| {
+                    (
+                        by_key[key(&project.uid, &t.uid).as_str()],
+                        f.deadline_chance,
+                    )
+                }),
+            ));
+            let finish = finish(forecasts.iter().map(|(_, f)| f));
+            ProjectForecast {
+                uid: project.uid.clone(),
+                name: project.name.clone(),
+                finish_dates: dates(&finish, calendar, Calendar::finish_date),
+                finish,
+                milestones: forecasts
+                    .into_iter()
+                    .filter(|(t, _)| {
+                        t.r#type == TaskType::Milestone
+                            || waited_for.contains(key(&project.uid, &t.uid).as_str())
+                    })
+                    .map(|(_, f)| f)
+                    .collect(),
+            }
+        })
+        .collect();
+
+    Ok(PortfolioForecast {
+        iterations: rs.iterations,
+        shared_people,
+        projects,
+        warnings,
+    })
+}
+
+fn project_names(projects: &[Project]) -> HashMap<&str, &str> {
+    HashMap::from_iter(projects.iter().map(|p| (p.uid.as_str(), p.name.as_str())))
+}
+
+/// Shows up as `Project/ID` in cycles and warnings
+fn with_project_id(task: Task, names: &HashMap<&str, &str>) -> Task {
+    let name = names
+        .get(task.project_uid.as_str())
+        .copied()
+        .unwrap_or_default();
+    Task {
+        id: format!("{}/{}", name, task.id),
+        ..task
+    }
+}
+
+/// Tasks that wait for each other across projects can't be forecast
+fn check_cycles(tasks: &[Task]) -> Result<(), Cycles> {
+    let cycles = roughly_sort_tasks(tasks.iter()).cycles;
+    match cycles.is_empty() {
+        true => Ok(()),
+        false => Err(Cycles(cycles)),
+    }
+}
+
+/// The results of the tasks of one of the simulated projects, by uid
+fn of_project(rs: SimulationResults, project_uid: &str) -> SimulationResults {
+    let prefix = key(project_uid, "");
+    fn strip<T>(map: HashMap<String, T>, prefix: &str) -> HashMap<String, T> {
+        map.into_iter()
+            .filter_map(|(k, v)| Some((k.strip_prefix(prefix)?.to_owned(), v)))
+            .collect()
+    }
+    SimulationResults {
+        task_stats: strip(rs.task_stats, &prefix),
+        iterations: rs.iterations,
+        unstaffed: rs
+            .unstaffed
+            .iter()
+            .filter_map(|k| Some(k.strip_prefix(&prefix)?.to_owned()))
+            .collect(),
+        criticality: strip(rs.criticality, &prefix),
+        sensitivity: strip(rs.sensitivity, &prefix),
+        deadline_chance: strip(rs.deadline_chance, &prefix),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project(uid: &str, name: &str) -> Project {
+        Project {
+            uid: uid.to_owned(),
+            name: name.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    fn task(project_uid: &str, uid: &str, id: &str) -> Task {
+        Task {
+            project_uid: project_uid.to_owned(),
+            uid: uid.to_owned(),
+            id: id.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn qualified_tasks_key_their_epic_and_dependencies() {
+        let task = Task {
+            parent: Some("e".to_owned()),
+            dependencies: [
+                ("a".to_owned(), Dependency::default()),
+                (
+                    "x".to_owned(),
+                    Dependency {
+                        project: Some("q".to_owned()),
+                        lag: 1.0,
+                        ..Default::default()
+                    },
+                ),
+            ]
+            .into(),
+            ..task("p", "t", "T")
+        };
+        let qualified = qualified(&task);
+        assert_eq!(qualified.uid, "p/t");
+        assert_eq!(qualified.parent.as_deref(), Some("p/e"));
+        assert_eq!(qualified.dependencies["p/a"], Dependency::default());
+        assert_eq!(
+            qualified.dependencies["q/x"],
+            Dependency {
+                lag: 1.0,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn tasks_are_found_by_id_or_project_and_id() {
+        let projects = HSTable::from_iter([project("p", "Web"), project("q", "Backend")]);
+        let tasks = HSTable::from_iter([
+            task("p", "a", "A"),
+            task("q", "b", "A"),
+            task("q", "c", "C"),
+        ]);
+        let find = |id| find_task(id, "p", &projects, &tasks).map(|t| t.uid.as_str());
+        assert_eq!(find("A"), Some("a"));
+        assert_eq!(find("Backend/A"), Some("b"));
+        assert_eq!(find(" backend / C "), Some("c"));
+        assert_eq!(find("q/C"), Some("c"));
+        assert_eq!(find("C"), None);
+        assert_eq!(find("Web/A"), None);
+        assert_eq!(find("Nope/A"), None);
+    }
+
+    #[test]
+    fn results_of_a_project_drop_the_key() {
+        let rs = SimulationResults {
+            task_stats: HashMap::new(),
+            iterations: 10,
+            unstaffed: vec!["p/a".to_owned(), "q/b".to_owned()],
+            criticality: HashMap::from([("p/a".to_owned(), 1.0), ("q/b".to_owned(), 0.5)]),
+            sensitivity: HashMap::new(),
+            deadline_chance: HashMap::from([("q/m".to_owned(), 0.9)]),
+        };
+        let rs = of_project(rs, "q");
+        assert_eq!(rs.unstaffed, ["b"]);
+        assert_eq!(rs.criticality, HashMap::from([("b".to_owned(), 0.5)]));
+        assert_eq!(rs.deadline_chance, HashMap::from([("m".to_owned(), 0.9)]));
+    }
+}
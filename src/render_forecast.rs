@@ -12,7 +12,8 @@ pub fn render_dist(start: &Quantogram, end: &Quantogram, days: &Range<u32>) -> S
 
     // For every day, sample the probability that we are working on that day
     // The probability is P(working|day) = P(start <= day) * (1 - P(end <= day))
-    let step_size = (1.0_f64).min(days.len() as f64 / 20.0);
+    // Tasks that take no time, like milestones at the start, still get one step
+    let step_size = (1.0_f64).min(days.len() as f64 / 20.0).max(0.05);
 
     let mut day = days.start as f64;
     while day <= days.end as f64 {
@@ -55,3 +56,48 @@ pub fn render_dist(start: &Quantogram, end: &Quantogram, days: &Range<u32>) -> S
         paths: vec![path],
     }
 }
+
+/// Height of one bar of the tornado chart, in SVG units
+pub const TORNADO_ROW: Coord = 24;
+
+/// Render correlations from -1 to 1 as horizontal bars around a center line, one row each
+///
+/// The first path has the positive bars, the second the negative ones and the third the center line.
+pub fn render_tornado(correlations: &[f64]) -> Svg {
+    const W: f64 = 100.0;
+    let bar = |row: usize, c: f64| -> Path {
+        let top = row as Coord * TORNADO_ROW + 4;
+        let bottom = (row as Coord + 1) * TORNADO_ROW - 4;
+        let x = (c * W) as Coord;
+        vec![
+            Segment::Move(0, top),
+            Segment::Line(x, top),
+            Segment::Line(x, bottom),
+            Segment::Line(0, bottom),
+            Segment::Return,
+        ]
+    };
+
+    let mut positive: Path = vec![];
+    let mut negative: Path = vec![];
+    for (row, c) in correlations.iter().enumerate() {
+        if *c >= 0.0 {
+            positive.extend(bar(row, *c));
+        } else {
+            negative.extend(bar(row, *c));
+        }
+    }
+    let height = correlations.len() as Coord * TORNADO_ROW;
+    let center = vec![
+        Segment::Move(0, 0),
+        Segment::Line(1, 0),
+        Segment::Line(1, height),
+        Segment::Line(0, height),
+        Segment::Return,
+    ];
+
+    Svg {
+        view_box: (-W as Coord, 0, 2 * W as Coord, height),
+        paths: vec![positive, negative, center],
+    }
+}
@@ -0,0 +1,249 @@
+//! Forecast summaries, as returned by the JSON API and printed by the command line tool
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
+
+use chrono::NaiveDate;
+use itertools::Itertools;
+use quantogram::Quantogram;
+use serde::Serialize;
+
+use crate::{
+    calendar::Calendar,
+    datamodel::{roughly_sort_tasks, Deadline, Distribution, Project, Task, TaskType},
+    forecast::SimulationResults,
+    portfolio::simulate_project,
+};
+
+/// The quantiles reported for every task
+pub const QUANTILES: [(&str, f64); 6] = [
+    ("p10", 0.1),
+    ("p25", 0.25),
+    ("p50", 0.5),
+    ("p75", 0.75),
+    ("p90", 0.9),
+    ("p95", 0.95),
+];
+
+#[derive(Serialize, Debug)]
+pub struct TaskForecast {
+    pub uid: String,
+    pub id: String,
+    pub title: String,
+    /// Uid of the epic, whose forecast spans those of its subtasks
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+    pub start: BTreeMap<&'static str, f64>,
+    pub finish: BTreeMap<&'static str, f64>,
+    /// Fraction of iterations in which the task held up the end of the project
+    pub criticality: f64,
+    /// How the duration of the task correlates with the finish of the project, -1 to 1
+    pub sensitivity: f64,
+    /// Only for milestones
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deadline: Option<Deadline>,
+    /// Chance of reaching the milestone by its deadline
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deadline_chance: Option<f64>,
+    /// Only when the project has a start date
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub start_dates: BTreeMap<&'static str, NaiveDate>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub finish_dates: BTreeMap<&'static str, NaiveDate>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Forecast {
+    /// How many times the project was simulated
+    pub iterations: u32,
+    pub finish: BTreeMap<&'static str, f64>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub finish_dates: BTreeMap<&'static str, NaiveDate>,
+    pub tasks: Vec<TaskForecast>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+/// A plan can't be forecast when some of its tasks depend on each other
+#[derive(Debug)]
+pub struct Cycles(pub Vec<Vec<String>>);
+
+impl fmt::Display for Cycles {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cycles: Vec<String> = self.0.iter().map(|c| c.join(" → ")).collect();
+        write!(f, "Tasks with a dependency cycle: {}", cycles.join(", "))
+    }
+}
+
+impl std::error::Error for Cycles {}
+
+/// Simulate the rest of a project from `today` and summarize when each task starts and finishes
+///
+/// The projects it waits for are simulated along with it, see `portfolio::upstream`.
+pub fn forecast(
+    project: &Project,
+    tasks: Vec<Task>,
+    upstream: &[(Project, Vec<Task>)],
+    today: NaiveDate,
+) -> Result<Forecast, Cycles> {
+    let sorted_tasks = roughly_sort_tasks(tasks.iter());
+    if !sorted_tasks.cycles.is_empty() {
+        return Err(Cycles(sorted_tasks.cycles));
+    }
+
+    let rs = simulate_project(project, &tasks, upstream, today)?;
+    let tasks_by_uid: HashMap<&str, &Task> = HashMap::from_iter(
+        sorted_tasks
+            .sorted_tasks
+            .iter()
+            .map(|t| (t.uid.as_str(), t)),
+    );
+    let tasks: Vec<TaskForecast> = sorted_tasks
+        .sorted_tasks
+        .iter()
+        .map(|task| task_forecast(task, &task.uid, &rs, &project.calendar))
+        .collect();
+    let finish = finish(&tasks);
+
+    Ok(Forecast {
+        warnings: estimate_warnings(tasks_by_uid.values().copied())
+            .into_iter()
+            .chain(staffing_warnings(&tasks_by_uid, &rs.unstaffed))
+            .chain(deadline_warnings(
+                project,
+                sorted_tasks
+                    .sorted_tasks
+                    .iter()
+                    .zip(tasks.iter().map(|t| t.deadline_chance)),
+            ))
+            .collect(),
+        iterations: rs.iterations,
+        finish_dates: dates(&finish, &project.calendar, Calendar::finish_date),
+        finish,
+        tasks,
+    })
+}
+
+/// Summarize the simulated start and finish of the task that is `key` in the results
+pub fn task_forecast(
+    task: &Task,
+    key: &str,
+    rs: &SimulationResults,
+    calendar: &Calendar,
+) -> TaskForecast {
+    let stats = &rs.task_stats[key];
+    let start = quantiles(&stats.start);
+    let finish = quantiles(&stats.end);
+    TaskForecast {
+        uid: task.uid.clone(),
+        id: task.id.clone(),
+        title: task.title.clone(),
+        parent: task.parent.clone(),
+        criticality: rs.criticality[key],
+        sensitivity: rs.sensitivity[key],
+        deadline: task.deadline.filter(|_| task.r#type == TaskType::Milestone),
+        // None without a deadline, or for a date when the project has no start date
+        deadline_chance: rs.deadline_chance.get(key).copied(),
+        start_dates: dates(&start, calendar, Calendar::start_date),
+        finish_dates: dates(&finish, calendar, Calendar::finish_date),
+        start,
+        finish,
+    }
+}
+
+fn quantiles(q: &Quantogram) -> BTreeMap<&'static str, f64> {
+    QUANTILES
+        .iter()
+        .filter_map(|(name, phi)| Some((*name, q.quantile(*phi)?)))
+        .collect()
+}
+
+pub fn dates(
+    offsets: &BTreeMap<&'static str, f64>,
+    calendar: &Calendar,
+    to_date: fn(&Calendar, f64) -> Option<NaiveDate>,
+) -> BTreeMap<&'static str, NaiveDate> {
+    offsets
+        .iter()
+        .filter_map(|(name, offset)| Some((*name, to_date(calendar, *offset)?)))
+        .collect()
+}
+
+/// A project finishes when its last task finishes
+pub fn finish<'a>(
+    tasks: impl IntoIterator<Item = &'a TaskForecast>,
+) -> BTreeMap<&'static str, f64> {
+    let mut finish: BTreeMap<&'static str, f64> = BTreeMap::new();
+    for task in tasks {
+        for (name, value) in &task.finish {
+            let f = finish.entry(name).or_insert(*value);
+            *f = f.max(*value);
+        }
+    }
+    finish
+}
+
+/// Three-point estimates that can't be used, and fall back to the risk
+pub fn estimate_warnings<'a>(tasks: impl Iterator<Item = &'a Task>) -> Vec<String> {
+    tasks
+        .filter(|t| t.r#type == TaskType::Task && t.distribution != Distribution::LogNormal)
+        .filter(|t| {
+            !matches!((t.optimistic, t.estimate, t.pessimistic),
+                (Some(o), Some(e), Some(p)) if o < p && (o..=p).contains(&e))
+        })
+        .sorted_by(|a, b| human_sort::compare(&a.id, &b.id))
+        .map(|t| {
+            format!(
+                "{} needs an optimistic, likely and pessimistic estimate in that order, the risk is used instead",
+                t.id
+            )
+        })
+        .collect()
+}
+
+/// Explain why tasks that nobody could work on were given to anyone
+pub fn staffing_warnings(tasks: &HashMap<&str, &Task>, unstaffed: &[String]) -> Vec<String> {
+    unstaffed
+        .iter()
+        .filter_map(|uid| tasks.get(uid.as_str()))
+        .sorted_by(|a, b| human_sort::compare(&a.id, &b.id))
+        .map(|task| match task.assignee {
+            Some(_) => format!(
+                "{} is assigned to someone who isn't on the project, anyone may work on it",
+                task.id
+            ),
+            None => format!(
+                "Nobody has the skills for {} ({}), anyone may work on it",
+                task.id,
+                task.skills.iter().join(", ")
+            ),
+        })
+        .collect()
+}
+
+/// Milestones that are less likely to make their deadline than the project asks for
+pub fn deadline_warnings<'a>(
+    project: &Project,
+    chances: impl Iterator<Item = (&'a Task, Option<f64>)>,
+) -> Vec<String> {
+    chances
+        .filter(|(t, _)| t.r#type == TaskType::Milestone)
+        .filter_map(|(t, chance)| Some((t, t.deadline?, chance)))
+        .sorted_by(|(a, _, _), (b, _, _)| human_sort::compare(&a.id, &b.id))
+        .filter_map(|(task, deadline, chance)| match chance {
+            None => Some(format!(
+                "{} is due on {}, give the project a start date to forecast it",
+                task.id, deadline
+            )),
+            Some(chance) if chance < project.confidence => Some(format!(
+                "{} has a {:.0}% chance of making its deadline {}, less than {:.0}%",
+                task.id,
+                chance * 100.0,
+                deadline,
+                project.confidence * 100.0
+            )),
+            Some(_) => None,
+        })
+        .collect()
+}
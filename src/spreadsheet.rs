@@ -0,0 +1,471 @@
+//! CSV import and export of the tasks of a project
+//!
+//! Tasks are matched by their ID, and dependencies are listed by ID, so a
+//! spreadsheet never has to know about uids. Dependencies on tasks of other
+//! projects stay out of it, and an import leaves them alone.
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Result;
+use chrono::NaiveDate;
+use itertools::Itertools;
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
+
+use crate::{
+    datamodel::{
+        parse_dependency, parse_skills, Deadline, Dependency, Distribution, Person, Risk, Task,
+        TaskStatus, TaskType, TaskUpdate,
+    },
+    ids::unique_id,
+};
+
+/// One row of the spreadsheet
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+struct Row {
+    #[serde(deserialize_with = "by_name")]
+    r#type: Option<TaskType>,
+    id: String,
+    title: String,
+    estimate: Option<f64>,
+    #[serde(deserialize_with = "by_name")]
+    risk: Option<Risk>,
+    #[serde(deserialize_with = "by_name")]
+    distribution: Option<Distribution>,
+    optimistic: Option<f64>,
+    pessimistic: Option<f64>,
+    sigma: Option<f64>,
+    /// IDs, separated by spaces or commas, with the kind and lag after a colon like `A:SS+2`
+    dependencies: String,
+    /// Separated by spaces or commas
+    skills: String,
+    /// Name of a person on the project
+    assignee: String,
+    #[serde(deserialize_with = "by_name")]
+    status: Option<TaskStatus>,
+    started: Option<NaiveDate>,
+    finished: Option<NaiveDate>,
+    remaining: Option<f64>,
+    /// Date or day a milestone is due
+    deadline: String,
+    /// ID of the epic the task is part of
+    parent: String,
+    priority: Option<u32>,
+    /// How many people can work on the task at once
+    max_people: Option<u32>,
+}
+
+/// Read a unit enum by the name it serializes to, ignoring case and with spaces for dashes
+fn by_name<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: DeserializeOwned,
+    D: Deserializer<'de>,
+{
+    let name = String::deserialize(deserializer)?;
+    if name.is_empty() {
+        return Ok(None);
+    }
+    serde_json::from_value(serde_json::Value::String(
+        name.to_lowercase().replace(' ', "-"),
+    ))
+    .map(Some)
+    .map_err(serde::de::Error::custom)
+}
+
+/// Write all tasks of a project as CSV, ordered by ID
+pub fn export_tasks(tasks: &[Task], people: &[Person]) -> Result<String> {
+    let ids: HashMap<&str, &str> =
+        HashMap::from_iter(tasks.iter().map(|t| (t.uid.as_str(), t.id.as_str())));
+    let names: HashMap<&str, &str> =
+        HashMap::from_iter(people.iter().map(|p| (p.uid.as_str(), p.name.as_str())));
+
+    let mut writer = csv::Writer::from_writer(vec![]);
+    for task in tasks
+        .iter()
+        .sorted_by(|a, b| human_sort::compare(&a.id, &b.id))
+    {
+        writer.serialize(Row {
+            r#type: Some(task.r#type.clone()),
+            id: task.id.clone(),
+            title: task.title.clone(),
+            estimate: task.estimate,
+            risk: task.risk,
+            distribution: Some(task.distribution),
+            optimistic: task.optimistic,
+            pessimistic: task.pessimistic,
+            sigma: task.sigma,
+            dependencies: task
+                .dependencies
+                .iter()
+                .filter_map(|(d, dependency)| Some((*ids.get(d.as_str())?, dependency)))
+                .sorted_by(|(a, _), (b, _)| human_sort::compare(a, b))
+                .map(
+                    |(id, dependency)| match *dependency == Dependency::default() {
+                        true => id.to_owned(),
+                        false => format!("{}:{}", id, dependency),
+                    },
+                )
+                .join(" "),
+            skills: task.skills.iter().join(" "),
+            assignee: task
+                .assignee
+                .as_deref()
+                .and_then(|a| names.get(a))
+                .map(|n| n.to_string())
+                .unwrap_or_default(),
+            status: Some(task.status),
+            started: task.started,
+            finished: task.finished,
+            remaining: task.remaining,
+            deadline: task.deadline.map(|d| d.to_string()).unwrap_or_default(),
+            parent: task
+                .parent
+                .as_deref()
+                .and_then(|p| ids.get(p))
+                .map(|id| id.to_string())
+                .unwrap_or_default(),
+            priority: task.priority,
+            max_people: task.max_people,
+        })?;
+    }
+    Ok(String::from_utf8(writer.into_inner()?)?)
+}
+
+/// What happened to the rows of an imported spreadsheet
+#[derive(Serialize, Debug, Default)]
+pub struct ImportReport {
+    pub created: usize,
+    pub updated: usize,
+    /// Rows that were skipped, or only partly imported
+    pub errors: Vec<String>,
+}
+
+/// Turn a spreadsheet into task updates
+///
+/// Rows with an ID that is already in the project update that task, all other
+/// rows create a new task. Columns that are missing from the spreadsheet are
+/// left alone, empty cells clear the value. Assignees are matched by name.
+pub fn import_tasks(
+    project_uid: &str,
+    existing: &[Task],
+    people: &[Person],
+    data: &str,
+) -> (Vec<TaskUpdate>, ImportReport) {
+    let mut report = ImportReport::default();
+
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(data.as_bytes());
+    // Lowercase the header row, so `ID` and `Title` work too
+    let headers: Vec<String> = match reader.headers() {
+        Ok(headers) => headers.iter().map(|h| h.to_lowercase()).collect(),
+        Err(e) => {
+            report
+                .errors
+                .push(format!("Can't read the header row: {}", e));
+            return (vec![], report);
+        }
+    };
+    reader.set_headers(csv::StringRecord::from(headers.clone()));
+    let columns: HashSet<String> = HashSet::from_iter(headers.iter().cloned());
+    let has = |column: &str| columns.contains(column);
+    if !has("id") && !has("title") {
+        report
+            .errors
+            .push("The spreadsheet needs an id or a title column".to_owned());
+        return (vec![], report);
+    }
+
+    // Find the uid of every row first, so rows can depend on rows further down
+    let mut uids: HashMap<String, String> =
+        HashMap::from_iter(existing.iter().map(|t| (t.id.clone(), t.uid.clone())));
+    let existing: HashMap<&str, &Task> =
+        HashMap::from_iter(existing.iter().map(|t| (t.uid.as_str(), t)));
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut row_errors: Vec<(usize, String)> = vec![];
+    let mut rows: Vec<(usize, String, Row)> = vec![];
+    for (i, record) in reader.deserialize::<Row>().enumerate() {
+        // The header is line 1
+        let line = i + 2;
+        let row = match record {
+            Ok(row) => row,
+            Err(e) => {
+                row_errors.push((line, csv_error(&e, &headers)));
+                continue;
+            }
+        };
+        if row.id.is_empty() && row.title.is_empty() {
+            continue;
+        }
+        let invalid = [
+            ("estimate", row.estimate),
+            ("remaining work", row.remaining),
+            ("optimistic estimate", row.optimistic),
+            ("pessimistic estimate", row.pessimistic),
+            ("sigma", row.sigma),
+        ]
+        .into_iter()
+        .find(|(_, v)| v.is_some_and(|v| !v.is_finite() || v < 0.0));
+        if let Some((name, Some(value))) = invalid {
+            row_errors.push((line, format!("invalid {} {}", name, value)));
+            continue;
+        }
+        if row.max_people == Some(0) {
+            row_errors.push((line, "invalid max people 0".to_owned()));
+            continue;
+        }
+        if !row.id.is_empty() && !seen.insert(row.id.clone()) {
+            row_errors.push((line, format!("{} is in the spreadsheet twice", row.id)));
+            continue;
+        }
+
+        let uid = match uids.get(&row.id) {
+            Some(uid) if !row.id.is_empty() => uid.clone(),
+            _ => {
+                let uid = unique_id();
+                if !row.id.is_empty() {
+                    uids.insert(row.id.clone(), uid.clone());
+                }
+                uid
+            }
+        };
+        rows.push((line, uid, row));
+    }
+
+    let mut updates = vec![];
+    for (line, uid, row) in rows {
+        let task = existing.get(uid.as_str()).copied();
+        match task {
+            Some(_) => report.updated += 1,
+            None => report.created += 1,
+        }
+
+        // Convert dependency ids into uids, like add-dependency in the task grid
+        let mut add_dependencies = vec![];
+        let mut remove_dependencies = vec![];
+        if has("dependencies") {
+            let mut deps = HashMap::new();
+            for word in row
+                .dependencies
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|id| !id.is_empty())
+            {
+                let (id, dependency) = match parse_dependency(word) {
+                    Ok(parsed) => parsed,
+                    Err(e) => {
+                        row_errors.push((line, e));
+                        continue;
+                    }
+                };
+                match uids.get(id) {
+                    Some(dep) => {
+                        deps.insert(dep.clone(), dependency);
+                    }
+                    None => row_errors.push((line, format!("no task with ID {}", id))),
+                }
+            }
+            let current = task.map(|t| t.dependencies.clone()).unwrap_or_default();
+            add_dependencies = deps
+                .iter()
+                .filter(|(uid, dependency)| current.get(*uid) != Some(dependency))
+                .map(|(uid, dependency)| (uid.clone(), dependency.clone()))
+                .collect();
+            remove_dependencies = current
+                .iter()
+                .filter(|(uid, d)| d.project.is_none() && !deps.contains_key(*uid))
+                .map(|(uid, _)| uid.clone())
+                .collect();
+        }
+
+        let assignee = match row.assignee.as_str() {
+            _ if !has("assignee") => None,
+            "" => Some(None),
+            name => match people.iter().find(|p| p.name.eq_ignore_ascii_case(name)) {
+                Some(person) => Some(Some(person.uid.clone())),
+                None => {
+                    row_errors.push((line, format!("nobody called {} is on the project", name)));
+                    None
+                }
+            },
+        };
+
+        let deadline = match row.deadline.as_str() {
+            _ if !has("deadline") => None,
+            "" => Some(None),
+            deadline => match deadline.parse::<Deadline>() {
+                Ok(deadline) => Some(Some(deadline)),
+                Err(e) => {
+                    row_errors.push((line, e));
+                    None
+                }
+            },
+        };
+
+        let parent = match row.parent.as_str() {
+            _ if !has("parent") => None,
+            "" => Some(None),
+            id => match uids.get(id) {
+                Some(parent) if *parent != uid => Some(Some(parent.clone())),
+                Some(_) => {
+                    row_errors.push((line, format!("{} can't be part of itself", id)));
+                    None
+                }
+                None => {
+                    row_errors.push((line, format!("no task with ID {}", id)));
+                    None
+                }
+            },
+        };
+
+        updates.push(TaskUpdate {
+            project_uid: project_uid.to_owned(),
+            uid,
+            r#type: has("type").then(|| row.r#type.unwrap_or_default()),
+            id: has("id").then_some(row.id),
+            title: has("title").then_some(row.title),
+            estimate: has("estimate").then_some(row.estimate),
+            risk: has("risk").then_some(row.risk),
+            distribution: has("distribution").then(|| row.distribution.unwrap_or_default()),
+            optimistic: has("optimistic").then_some(row.optimistic),
+            pessimistic: has("pessimistic").then_some(row.pessimistic),
+            sigma: has("sigma").then_some(row.sigma),
+            add_dependencies,
+            remove_dependencies,
+            skills: has("skills").then(|| parse_skills(&row.skills)),
+            assignee,
+            status: has("status").then(|| row.status.unwrap_or_default()),
+            started: has("started").then_some(row.started),
+            finished: has("finished").then_some(row.finished),
+            remaining: has("remaining").then_some(row.remaining),
+            deadline,
+            parent,
+            priority: has("priority").then_some(row.priority),
+            max_people: has("max_people").then_some(row.max_people),
+        });
+    }
+
+    report.errors.extend(
+        row_errors
+            .into_iter()
+            .sorted_by_key(|(line, _)| *line)
+            .map(|(line, error)| format!("Row {}: {}", line, error)),
+    );
+    (updates, report)
+}
+
+/// csv errors mention byte offsets, which mean nothing to someone with a spreadsheet
+fn csv_error(e: &csv::Error, headers: &[String]) -> String {
+    match e.kind() {
+        csv::ErrorKind::Deserialize { err, .. } => {
+            match err.field().and_then(|f| headers.get(f as usize)) {
+                Some(column) => format!("{}: {}", column, err.kind()),
+                None => err.kind().to_string(),
+            }
+        }
+        csv::ErrorKind::UnequalLengths {
+            expected_len, len, ..
+        } => format!("expected {} columns, found {}", expected_len, len),
+        _ => e.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datamodel::DependencyKind;
+
+    fn tasks() -> Vec<Task> {
+        let task = |uid: &str| Task {
+            project_uid: "p".to_owned(),
+            uid: uid.to_owned(),
+            id: uid.to_uppercase(),
+            title: format!("Task {}", uid),
+            ..Default::default()
+        };
+        vec![
+            Task {
+                estimate: Some(3.0),
+                risk: Some(Risk::High),
+                skills: parse_skills("rust, sql"),
+                assignee: Some("person-2".to_owned()),
+                status: TaskStatus::InProgress,
+                started: NaiveDate::from_ymd_opt(2026, 1, 5),
+                remaining: Some(1.5),
+                priority: Some(1),
+                ..task("a")
+            },
+            Task {
+                estimate: Some(5.0),
+                distribution: Distribution::Pert,
+                optimistic: Some(4.0),
+                pessimistic: Some(9.0),
+                dependencies: [
+                    ("a".to_owned(), Dependency::default()),
+                    (
+                        "c".to_owned(),
+                        Dependency {
+                            kind: DependencyKind::StartToStart,
+                            lag: 2.0,
+                            project: None,
+                        },
+                    ),
+                ]
+                .into(),
+                parent: Some("c".to_owned()),
+                max_people: Some(2),
+                ..task("b")
+            },
+            Task {
+                r#type: TaskType::Milestone,
+                deadline: Some(Deadline::Day(20.0)),
+                ..task("c")
+            },
+        ]
+    }
+
+    #[test]
+    fn exported_tasks_import_unchanged() {
+        let people = Person::team(2);
+        let tasks = tasks();
+        let csv = export_tasks(&tasks, &people).unwrap();
+
+        let (updates, report) = import_tasks("p", &tasks, &people, &csv);
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert_eq!((report.created, report.updated), (0, 3));
+        let by_uid: HashMap<&str, &Task> = tasks.iter().map(|t| (t.uid.as_str(), t)).collect();
+        for update in updates {
+            let task = by_uid[update.uid.as_str()];
+            assert_eq!(&update.apply(task), task);
+        }
+    }
+
+    #[test]
+    fn exported_tasks_import_into_an_empty_project() {
+        let people = Person::team(2);
+        let csv = export_tasks(&tasks(), &people).unwrap();
+
+        let (updates, report) = import_tasks("q", &[], &people, &csv);
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert_eq!((report.created, report.updated), (3, 0));
+        let imported = updates
+            .into_iter()
+            .enumerate()
+            .map(|(i, update)| update.upsert(None, i))
+            .collect_vec();
+        assert_eq!(export_tasks(&imported, &people).unwrap(), csv);
+    }
+
+    #[test]
+    fn bad_rows_are_reported_by_line() {
+        let csv = "ID,Estimate,Dependencies\nA,-1,\nB,2,Z\nB,3,\n";
+        let (updates, report) = import_tasks("p", &[], &[], csv);
+        assert_eq!(updates.len(), 1);
+        assert_eq!(
+            report.errors,
+            [
+                "Row 2: invalid estimate -1",
+                "Row 3: no task with ID Z",
+                "Row 4: B is in the spreadsheet twice",
+            ]
+        );
+    }
+}
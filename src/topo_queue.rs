@@ -1,6 +1,7 @@
-use std::collections::{hash_map::Entry, HashMap, HashSet};
+use std::collections::{hash_map::Entry, BTreeSet, HashMap, HashSet};
 
 use itertools::Itertools;
+use ordered_float::OrderedFloat;
 
 type IdSet = HashSet<String>;
 type TaskMap = HashMap<String, IdSet>;
@@ -9,11 +10,14 @@ type TaskMap = HashMap<String, IdSet>;
 pub struct TopoQueue {
     current_deps: TaskMap,
     unblocks: TaskMap,
-    available: HashSet<String>,
+    /// Lowest first, tasks without a rank count as 0
+    ranks: HashMap<String, f64>,
+    /// Ordered by rank and then by name, so picking from it with a seeded rng is reproducible
+    available: BTreeSet<(OrderedFloat<f64>, String)>,
 }
 
-impl TopoQueue {
-    pub fn from_iter(iter: impl IntoIterator<Item = (String, HashSet<String>)>) -> Self {
+impl FromIterator<(String, IdSet)> for TopoQueue {
+    fn from_iter<I: IntoIterator<Item = (String, IdSet)>>(iter: I) -> Self {
         let deps = HashMap::from_iter(iter);
         let mut ret = TopoQueue {
             current_deps: deps.clone(),
@@ -37,18 +41,44 @@ impl TopoQueue {
 
         ret
     }
+}
 
+impl TopoQueue {
     pub fn is_empty(&self) -> bool {
         self.available.len() + self.current_deps.len() == 0
     }
 
+    /// Hand out available items by rank, lowest first
+    pub fn with_ranks(mut self, ranks: HashMap<String, f64>) -> Self {
+        self.ranks = ranks;
+        let available = std::mem::take(&mut self.available);
+        self.available = available
+            .into_iter()
+            .map(|(_, k)| (self.rank(&k), k))
+            .collect();
+        self
+    }
+
+    fn rank(&self, x: &str) -> OrderedFloat<f64> {
+        OrderedFloat(self.ranks.get(x).copied().unwrap_or(0.0))
+    }
+
+    /// Lowest rank first
     pub fn available(&self) -> impl Iterator<Item = &String> {
-        self.available.iter()
+        self.available.iter().map(|(_, k)| k)
+    }
+
+    /// The available items that share the lowest rank, all of them without ranks
+    pub fn next_available(&self) -> impl Iterator<Item = &String> {
+        let first = self.available.first().map(|(r, _)| *r);
+        self.available
+            .iter()
+            .take_while(move |(r, _)| Some(*r) == first)
+            .map(|(_, k)| k)
     }
 
     pub fn remove(&mut self, x: &str) {
-        assert!(self.available.contains(x));
-        self.available.remove(x);
+        assert!(self.available.remove(&(self.rank(x), x.to_owned())));
         if let Some(unblocks) = self.unblocks.remove(x) {
             for t in unblocks {
                 self.current_deps.get_mut(&t).unwrap().remove(x);
@@ -65,9 +95,9 @@ impl TopoQueue {
             .map(|(k, _)| k)
             .cloned()
             .collect_vec();
-        for k in &available {
-            self.current_deps.remove(k);
+        for k in available {
+            self.current_deps.remove(&k);
+            self.available.insert((self.rank(&k), k));
         }
-        self.available.extend(available);
     }
 }
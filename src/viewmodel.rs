@@ -1,10 +1,13 @@
+use chrono::NaiveDate;
+use rocket::fs::TempFile;
 use serde::Serialize;
 
-use crate::datamodel::{Risk, TaskType};
+use planny::datamodel::{Distribution, Risk, Scheduling, TaskStatus, TaskType};
 use std::option::Option;
 
 #[derive(FromForm, Debug, Clone)]
 pub struct TaskForm {
+    #[allow(dead_code)]
     pub add: Option<bool>,
 
     pub uid: String,
@@ -15,14 +18,104 @@ pub struct TaskForm {
     // The following 2 do not exist for milestones
     pub estimate: Option<String>,
     pub risk: Option<Risk>,
+    pub distribution: Option<Distribution>,
+    pub optimistic: Option<String>,
+    pub pessimistic: Option<String>,
+    pub sigma: Option<String>,
+    pub skills: Option<String>,
+    /// Uid of a person, empty for anyone with the skills
+    pub assignee: Option<String>,
+    pub status: Option<TaskStatus>,
+    /// `YYYY-MM-DD`, only there for tasks that started
+    pub started: Option<String>,
+    pub finished: Option<String>,
+    pub remaining: Option<String>,
+    /// Only for milestones, `YYYY-MM-DD` or a number of days
+    pub deadline: Option<String>,
+    /// ID of the epic, empty for none
+    pub parent: Option<String>,
+    pub priority: Option<String>,
+    #[field(name = "max-people")]
+    pub max_people: Option<String>,
 
     #[field(name = "add-dependency")]
     pub add_dependency: Option<String>,
 }
 
 #[derive(FromForm, Debug, Clone)]
-pub struct ProjectPeopleForm {
-    pub people: u32,
+pub struct DependencyForm {
+    /// Kind and lag, like `SS+2`
+    pub link: String,
+}
+
+#[derive(FromForm, Debug, Clone)]
+pub struct PersonForm {
+    #[allow(dead_code)]
+    pub add: Option<bool>,
+
+    pub uid: String,
+    pub name: String,
+    /// Fraction of full time
+    pub capacity: String,
+    pub skills: String,
+    /// `YYYY-MM-DD`, empty when they are there from the start
+    pub start: String,
+    /// One range per line, a start and an end date, or a single day
+    pub vacations: String,
+    /// Uid of someone in the pool, empty when they only work on this project
+    pub pool: Option<String>,
+}
+
+#[derive(FromForm, Debug, Clone)]
+pub struct PoolMemberForm {
+    #[allow(dead_code)]
+    pub add: Option<bool>,
+
+    pub uid: String,
+    pub name: String,
+    /// Fraction of full time
+    pub capacity: String,
+}
+
+#[derive(FromForm, Debug, Clone)]
+pub struct ProjectSeedForm {
+    /// Pick a new random seed when left out
+    pub seed: Option<u64>,
+}
+
+#[derive(FromForm, Debug, Clone)]
+pub struct ProjectIterationsForm {
+    pub iterations: u32,
+    /// In percent, empty to always run all iterations
+    pub tolerance: Option<String>,
+}
+
+#[derive(FromForm, Debug, Clone)]
+pub struct ProjectConfidenceForm {
+    /// In percent
+    pub confidence: Option<f64>,
+}
+
+#[derive(FromForm, Debug, Clone)]
+pub struct ProjectEfficiencyLossForm {
+    /// In percent
+    #[field(name = "efficiency-loss")]
+    pub efficiency_loss: Option<f64>,
+}
+
+#[derive(FromForm, Debug, Clone)]
+pub struct ProjectSchedulingForm {
+    pub scheduling: Scheduling,
+}
+
+#[derive(FromForm, Debug, Clone)]
+pub struct CalendarForm {
+    /// `YYYY-MM-DD`, empty for no start date
+    pub start: String,
+    #[field(name = "working-day")]
+    pub working_days: Vec<String>,
+    /// One date per line, anything after the date is ignored
+    pub holidays: String,
 }
 
 #[derive(FromForm, Debug, Clone)]
@@ -31,6 +124,17 @@ pub struct ProjectNameForm {
     pub project_name: String,
 }
 
+#[derive(FromForm, Debug, Clone)]
+pub struct SnapshotForm {
+    #[field(name = "snapshot-name")]
+    pub name: String,
+}
+
+#[derive(FromForm, Debug)]
+pub struct CsvImportForm<'r> {
+    pub file: TempFile<'r>,
+}
+
 #[derive(Serialize, Debug, Clone)]
 pub struct Choice {
     pub value: String,
@@ -45,12 +149,53 @@ pub struct TaskView {
     pub title: String,
     pub estimate: Option<f64>,
     pub risk: Option<Risk>,
+    pub distribution: Distribution,
+    pub optimistic: Option<f64>,
+    pub pessimistic: Option<f64>,
+    pub sigma: Option<f64>,
+    pub skills: String,
+    pub assignee: String,
+    pub status: TaskStatus,
+    pub started: Option<NaiveDate>,
+    pub finished: Option<NaiveDate>,
+    pub remaining: Option<f64>,
+    pub deadline: String,
+    /// ID of the epic
+    pub parent: String,
+    /// How many epics up the task is nested
+    pub depth: usize,
+    /// An epic has subtasks
+    pub subtasks: usize,
+    /// Uids of the epics the task is part of, to collapse them
+    pub ancestors: Vec<String>,
+    pub priority: Option<u32>,
+    pub max_people: Option<u32>,
     pub dependencies: Vec<TaskDependencyView>,
 }
 
+#[derive(Serialize, Debug, Clone)]
+pub struct PersonView {
+    pub uid: String,
+    pub name: String,
+    pub capacity: f64,
+    pub skills: String,
+    pub start: String,
+    pub vacations: String,
+    pub pool: String,
+}
+
+/// A tick on the ruler of the forecast, days are working days from the start
+#[derive(Serialize, Debug, Clone)]
+pub struct RulerTick {
+    pub day: u32,
+    pub label: Option<String>,
+}
+
 #[derive(Serialize, Debug, Clone)]
 pub struct TaskDependencyView {
     pub uid: String,
     pub id: String,
     pub title: String,
+    /// Kind and lag, like `SS+2`
+    pub link: String,
 }